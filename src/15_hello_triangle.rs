@@ -1,3 +1,5 @@
+extern crate backtrace;
+extern crate dirs;
 extern crate env_logger;
 #[cfg(feature = "dx12")]
 extern crate gfx_backend_dx12 as back;
@@ -7,101 +9,286 @@ extern crate gfx_backend_metal as back;
 extern crate gfx_backend_vulkan as back;
 extern crate gfx_hal as hal;
 extern crate glsl_to_spirv;
+#[macro_use]
+extern crate log;
+extern crate png;
 extern crate winit;
 
+mod common;
+
+use common::camera::{self, Camera, CameraController};
+use common::frame_timer::{FrameTimer, FrameTiming};
+use common::input::{Bindings, Input, InputEvent};
+use common::leak;
+use common::memory::{self, Allocation, MemoryAllocator, ResourceKind, Strategy};
+use common::pipeline::{self, Pipeline, PipelineBuilder};
+use common::profile;
+use common::query::{DrawQueries, DrawStatistics, FrameCommandBuffer, TimestampQueries};
+use common::render_graph::{
+    CompiledGraph, PassBuilder, PassId, RenderGraph, RenderGraphBuilder, ResourceId,
+};
+use common::replay::{self, InputRecorder, InputReplay};
+use common::resource::{self, DeviceRef};
+use common::screenshot::{self, FrameOutput, Screenshots};
+use common::settings::Settings;
+use common::timestep::{self, FixedTimestep};
+use common::upload::UploadRing;
+use common::window_mode::{self, WindowMode, WindowModes};
+use hal::memory::Properties;
+use hal::pso::DescriptorPool;
 use hal::{
     command, format, image, pass, pool, pso, queue, window, Adapter, Backbuffer, Backend,
-    Capability, Device, Features, Gpu, Graphics, Instance, PhysicalDevice, Primitive, QueueFamily,
-    Surface, Swapchain, SwapchainConfig,
+    Capability, Device, Features, Gpu, Graphics, Instance, PhysicalDevice, QueueFamily, Surface,
+    Swapchain, SwapchainConfig,
 };
-use std::io::Read;
-use winit::{dpi, ControlFlow, Event, EventsLoop, Window, WindowBuilder, WindowEvent};
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+use winit::{dpi, Event, EventsLoop, Window, WindowBuilder, WindowEvent, WindowId};
 
 static WINDOW_NAME: &str = "15_hello_triangle";
-const MAX_FRAMES_IN_FLIGHT: usize = 2;
+// our own header in front of the driver's cache data: magic, adapter vendor id, adapter device id
+const PIPELINE_CACHE_MAGIC: [u8; 4] = *b"GHTC";
+const PIPELINE_CACHE_HEADER_SIZE: usize = 4 + 8 + 8;
+// overlapping copies of the triangle as (offset x, offset y, scale), drawn back to front
+const OBJECTS: [[f32; 3]; 3] = [[-0.25, -0.1, 1.0], [0.0, 0.0, 0.8], [0.25, 0.1, 0.6]];
+// bytes of per frame data the upload ring reserves for each frame in flight
+const UPLOAD_FRAME_SIZE: u64 = 64 * 1024;
+// how long to sleep for when no window can be drawn to, e.g. because they're all minimized
+const IDLE_SLEEP_MS: u64 = 10;
+// size of the image frames are recorded to, the same as the window's initial size
+const RECORD_EXTENT: window::Extent2D = window::Extent2D {
+    width: 1024,
+    height: 768,
+};
+
+// opens another window showing the same scene
+const OPEN_WINDOW: &str = "open_window";
+
+// in the format read by `Bindings::parse`
+const DEFAULT_BINDINGS: &str = "\
+open_window = F2
+";
 
 fn main() {
     env_logger::init();
-    let mut application = HelloTriangleApplication::init();
+    let settings = Settings::from_args();
+    if let Some(ref path) = settings.dump_graph {
+        // there's no surface to pick a format from, so use the one picked when it has no preference
+        let (compiled_graph, _, _) =
+            HelloTriangleApplication::create_render_graph(format::Format::Rgba8Srgb, true);
+        if let Err(err) = compiled_graph.write_dot(path) {
+            warn!(
+                "could not write render graph to {}: {}",
+                path.display(),
+                err
+            );
+        }
+        return;
+    }
+
+    if settings.record_frames.is_some() {
+        unsafe {
+            HelloTriangleApplication::record_offscreen(&settings);
+        }
+        return;
+    }
+
+    let trace = settings.trace.clone();
+    if trace.is_some() {
+        profile::enable();
+    }
+
+    let mut application = HelloTriangleApplication::init(settings);
     application.run();
     unsafe {
         application.clean_up();
     }
+
+    if let Some(trace) = trace {
+        if let Err(err) = profile::write_trace(&trace) {
+            warn!("could not write trace to {}: {}", trace.display(), err);
+        }
+    }
 }
 
-struct WindowState {
-    events_loop: Option<EventsLoop>,
+// a window and everything presenting to it; the device and what's drawn are shared by all of them
+struct WindowTarget {
+    // frame in flight that last rendered to each swapchain image
+    images_in_flight: Vec<Option<usize>>,
+    render_finished_semaphores: Vec<resource::Semaphore>,
+    image_available_semaphores: Vec<resource::Semaphore>,
+    // one per frame in flight, from that frame's shared pool
+    command_buffers: Vec<FrameCommandBuffer>,
+    // the backbuffer resource and the pass drawing the scene into it
+    backbuffer: ResourceId,
+    main_pass: PassId,
+    render_graph: RenderGraph,
+    frame_images: Vec<(<back::Backend as Backend>::Image, resource::ImageView)>,
+    screenshots: Screenshots,
+    // what the swapchain images can be used for, beyond being rendered to
+    swapchain_usage: image::Usage,
+    extent: window::Extent2D,
+    // the window's extent in the run being replayed, the aspect ratio follows it so the replay
+    // draws the same frames whatever size the window has now
+    replay_extent: Option<window::Extent2D>,
+    // only empty while the swapchain is being recreated, or once the surface changed to a format
+    // the shared pipeline can't draw to, which closes the window
+    swapchain: Option<resource::Swapchain>,
+    recreate_swapchain: bool,
+    surface: <back::Backend as Backend>::Surface,
+    modes: WindowModes,
     window: Window,
 }
 
+// a swapchain just created for a window, with views of its images
+struct SwapchainImages {
+    swapchain: resource::Swapchain,
+    frame_images: Vec<(<back::Backend as Backend>::Image, resource::ImageView)>,
+    extent: window::Extent2D,
+    format: format::Format,
+    // what the images can be used for, beyond being rendered to
+    usage: image::Usage,
+}
+
 struct HalState {
-    in_flight_fences: Vec<<back::Backend as Backend>::Fence>,
-    render_finished_semaphores: Vec<<back::Backend as Backend>::Semaphore>,
-    image_available_semaphores: Vec<<back::Backend as Backend>::Semaphore>,
-    submission_command_buffers:
-        Vec<command::CommandBuffer<back::Backend, Graphics, command::MultiShot, command::Primary>>,
-    command_pool: pool::CommandPool<back::Backend, Graphics>,
-    swapchain_framebuffers: Vec<<back::Backend as Backend>::Framebuffer>,
-    gfx_pipeline: <back::Backend as Backend>::GraphicsPipeline,
-    descriptor_set_layouts: Vec<<back::Backend as Backend>::DescriptorSetLayout>,
-    pipeline_layout: <back::Backend as Backend>::PipelineLayout,
-    render_pass: <back::Backend as Backend>::RenderPass,
-    frame_images: Vec<(
-        <back::Backend as Backend>::Image,
-        <back::Backend as Backend>::ImageView,
-    )>,
-    _format: format::Format,
-    swapchain: <back::Backend as Backend>::Swapchain,
+    // signalled once every window's commands for the frame have executed
+    in_flight_fences: Vec<resource::Fence>,
+    // latest results of the draw queries, one per object
+    draw_statistics: Vec<DrawStatistics>,
+    // queries are only made in the first window drawn each frame
+    draw_queries: Option<DrawQueries>,
+    // None if the device can't time the render pass
+    timestamp_queries: Option<TimestampQueries>,
+    // one pool per frame in flight, every window records its buffer for the frame from it
+    frame_command_pools: Vec<resource::CommandPool>,
+    // created for the first window's render pass, which every window's is compatible with as
+    // they all use the same format
+    pipeline: Pipeline,
+    pipeline_cache: resource::PipelineCache,
+    // bound with the offset of the frame's uniforms in the upload ring
+    frame_descriptor_set: <back::Backend as Backend>::DescriptorSet,
+    descriptor_pool: resource::DescriptorPool,
+    upload_ring: UploadRing,
+    memory_allocator: MemoryAllocator,
+    format: format::Format,
     command_queues: Vec<queue::CommandQueue<back::Backend, Graphics>>,
-    device: <back::Backend as Backend>::Device,
-    _surface: <back::Backend as Backend>::Surface,
-    _adapter: Adapter<back::Backend>,
-    _instance: back::Instance,
+    queue_family: queue::QueueFamilyId,
+    // everything above holds a reference to the device and is destroyed when dropped, in
+    // declaration order
+    device: DeviceRef,
+    adapter: Adapter<back::Backend>,
+    instance: back::Instance,
 }
 
 impl HalState {
-    unsafe fn clean_up(self) {
-        let device = &self.device;
-
-        for fence in self.in_flight_fences {
-            device.destroy_fence(fence)
+    // every window has to be closed first
+    unsafe fn clean_up(mut self) {
+        self.device.wait_idle().expect("Queues are not going idle!");
+        self.upload_ring.release(&mut self.memory_allocator);
+
+        HelloTriangleApplication::save_pipeline_cache(
+            &self.adapter,
+            &self.device,
+            &self.pipeline_cache,
+        );
+        self.memory_allocator.log_stats();
+
+        // everything owned by the hal state is gone after this, anything still alive was
+        // created somewhere else and never dropped
+        drop(self);
+        let leaked = leak::report();
+        if leaked > 0 {
+            warn!("{} device objects were not destroyed", leaked);
         }
+    }
+}
 
-        for semaphore in self.render_finished_semaphores {
-            device.destroy_semaphore(semaphore)
-        }
+// per frame shader data, matches `FrameData` in 15_shader_base.vert
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FrameUniforms {
+    view_projection: [[f32; 4]; 4],
+    pulse: f32,
+}
 
-        for semaphore in self.image_available_semaphores {
-            device.destroy_semaphore(semaphore)
-        }
+// what every command buffer of a frame draws with
+#[derive(Clone, Copy)]
+struct FrameContext<'a> {
+    frame: usize,
+    pipeline: &'a Pipeline,
+    frame_descriptor_set: &'a <back::Backend as Backend>::DescriptorSet,
+    scene: &'a Scene,
+}
 
-        device.destroy_command_pool(self.command_pool.into_raw());
+// the image a command buffer draws to, through the render graph that owns it
+struct DrawTarget<'a> {
+    render_graph: &'a mut RenderGraph,
+    backbuffer: ResourceId,
+    main_pass: PassId,
+    image_index: usize,
+    image: &'a <back::Backend as Backend>::Image,
+    extent: window::Extent2D,
+    screenshots: &'a Screenshots,
+}
 
-        for framebuffer in self.swapchain_framebuffers {
-            device.destroy_framebuffer(framebuffer);
-        }
+// simulation state, advanced in fixed steps by `update`
+#[derive(Clone, Copy, Default)]
+struct Scene {
+    pulse_phase: f32,
+    camera: Camera,
+}
 
-        device.destroy_graphics_pipeline(self.gfx_pipeline);
+impl Scene {
+    fn update(&mut self, delta: f32) {
+        self.pulse_phase += delta;
+    }
 
-        for descriptor_set_layout in self.descriptor_set_layouts {
-            device.destroy_descriptor_set_layout(descriptor_set_layout);
+    fn interpolate(&self, next: &Scene, alpha: f32) -> Scene {
+        Scene {
+            pulse_phase: self.pulse_phase + (next.pulse_phase - self.pulse_phase) * alpha,
+            camera: self.camera.interpolate(&next.camera, alpha),
         }
+    }
 
-        device.destroy_pipeline_layout(self.pipeline_layout);
-
-        device.destroy_render_pass(self.render_pass);
+    fn pulse(&self) -> f32 {
+        self.pulse_phase.sin()
+    }
 
-        for (_, image_view) in self.frame_images.into_iter() {
-            device.destroy_image_view(image_view);
+    fn frame_uniforms(&self, aspect: f32) -> FrameUniforms {
+        FrameUniforms {
+            view_projection: self.camera.view_projection(aspect),
+            pulse: self.pulse(),
         }
+    }
 
-        device.destroy_swapchain(self.swapchain);
+    // slowly pulse the background so it's obvious the frame is recorded every time
+    fn clear_color(&self) -> [f32; 4] {
+        [0.0, 0.0, 0.1 + 0.1 * self.pulse(), 1.0]
     }
 }
 
 struct HelloTriangleApplication {
+    camera_controller: CameraController,
+    frame_timer: FrameTimer,
+    input: Input,
+    input_recorder: Option<InputRecorder>,
+    input_replay: Option<InputReplay>,
+    previous_scene: Scene,
+    scene: Scene,
+    settings: Settings,
+    // the one input was last focused on, window modes and screenshots apply to it
+    focused_window: Option<WindowId>,
+    // the one the last cursor position was reported in
+    cursor_window: Option<WindowId>,
+    // in the order they were opened, the application exits once the last one is closed; they
+    // have to be gone before the hal state
+    windows: Vec<WindowTarget>,
     hal_state: HalState,
-    window_state: WindowState,
+    events_loop: Option<EventsLoop>,
 }
 
 #[derive(Default)]
@@ -116,79 +303,391 @@ impl QueueFamilyIds {
 }
 
 impl HelloTriangleApplication {
-    pub fn init() -> HelloTriangleApplication {
-        let window_state = HelloTriangleApplication::init_window();
-        let hal_state = unsafe { HelloTriangleApplication::init_hal(&window_state.window) };
+    pub fn init(mut settings: Settings) -> HelloTriangleApplication {
+        let events_loop = EventsLoop::new();
+        let (window, modes) =
+            HelloTriangleApplication::init_window(&events_loop, settings.window_mode);
+        let (mut hal_state, first_window) =
+            unsafe { HelloTriangleApplication::init_hal(window, modes, &mut settings) };
+
+        let mut windows = vec![first_window];
+        for _ in 1..settings.windows {
+            windows.extend(unsafe {
+                HelloTriangleApplication::open_window(
+                    &mut hal_state,
+                    &events_loop,
+                    settings.window_mode,
+                )
+            });
+        }
 
         HelloTriangleApplication {
+            camera_controller: HelloTriangleApplication::create_camera_controller(),
+            frame_timer: FrameTimer::new(settings.timings_csv.is_some()),
+            input: Input::new(HelloTriangleApplication::load_bindings(&settings)),
+            input_recorder: HelloTriangleApplication::create_input_recorder(&settings),
+            input_replay: HelloTriangleApplication::load_input_replay(&settings),
+            previous_scene: Scene::default(),
+            scene: Scene::default(),
+            settings,
+            focused_window: None,
+            cursor_window: None,
+            windows,
             hal_state,
-            window_state,
+            events_loop: Some(events_loop),
         }
     }
 
-    fn init_window() -> WindowState {
-        let events_loop = EventsLoop::new();
+    fn create_camera_controller() -> CameraController {
+        CameraController::orbit([0.0, 0.0, 0.0], 2.0)
+    }
+
+    fn load_bindings(settings: &Settings) -> Bindings {
+        let mut bindings =
+            Bindings::parse(camera::DEFAULT_BINDINGS).expect("default bindings are invalid!");
+        bindings.merge(
+            Bindings::parse(screenshot::DEFAULT_BINDINGS).expect("default bindings are invalid!"),
+        );
+        bindings.merge(
+            Bindings::parse(window_mode::DEFAULT_BINDINGS).expect("default bindings are invalid!"),
+        );
+        bindings.merge(Bindings::parse(DEFAULT_BINDINGS).expect("default bindings are invalid!"));
+        if let Some(ref path) = settings.bindings {
+            match Bindings::load(path) {
+                Ok(loaded) => bindings.merge(loaded),
+                Err(err) => warn!(
+                    "could not load bindings from {}, using the defaults: {}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+        bindings
+    }
+
+    fn create_input_recorder(settings: &Settings) -> Option<InputRecorder> {
+        let path = settings.record_input.as_ref()?;
+        match InputRecorder::create(path) {
+            Ok(recorder) => Some(recorder),
+            Err(err) => {
+                warn!("could not record input to {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    // a replay that can't be loaded is fatal, running with live input instead would be confusing
+    fn load_input_replay(settings: &Settings) -> Option<InputReplay> {
+        let path = settings.replay_input.as_ref()?;
+        let replay = InputReplay::load(path).unwrap_or_else(|err| {
+            panic!(
+                "could not load input replay from {}: {}",
+                path.display(),
+                err
+            )
+        });
+        info!(
+            "replaying {} frames from {}",
+            replay.frames_left(),
+            path.display()
+        );
+        Some(replay)
+    }
+
+    // the size is logical, so the window covers the same part of the screen at any dpi
+    fn init_window(events_loop: &EventsLoop, mode: WindowMode) -> (Window, WindowModes) {
         let window_builder = WindowBuilder::new()
             .with_dimensions(dpi::LogicalSize::new(1024., 768.))
             .with_title(WINDOW_NAME.to_string());
-        let window = window_builder.build(&events_loop).unwrap();
+        let window = window_builder.build(events_loop).unwrap();
 
-        WindowState {
-            events_loop: Some(events_loop),
-            window,
+        let mut modes = WindowModes::new();
+        modes.set(&window, mode);
+        (window, modes)
+    }
+
+    // swapchains are sized in physical pixels, windows in logical ones
+    fn window_extent(window: &Window) -> window::Extent2D {
+        let size = window
+            .get_inner_size()
+            .unwrap_or_else(|| dpi::LogicalSize::new(0.0, 0.0))
+            .to_physical(window.get_hidpi_factor());
+        window::Extent2D {
+            width: size.width.round() as u32,
+            height: size.height.round() as u32,
         }
     }
 
-    unsafe fn init_hal(window: &Window) -> HalState {
-        let instance = HelloTriangleApplication::create_instance();
-        let mut adapter = HelloTriangleApplication::pick_adapter(&instance);
-        let mut surface = HelloTriangleApplication::create_surface(&instance, window);
-        let (device, command_queues, queue_type, qf_id) =
-            HelloTriangleApplication::create_device_with_graphics_queues(&mut adapter, &surface);
-        let (swapchain, extent, backbuffer, format) =
-            HelloTriangleApplication::create_swap_chain(&adapter, &device, &mut surface, None);
-        let frame_images =
-            HelloTriangleApplication::create_image_views(backbuffer, format, &device);
-        let render_pass = HelloTriangleApplication::create_render_pass(&device, Some(format));
-        let (descriptor_set_layouts, pipeline_layout, gfx_pipeline) =
-            HelloTriangleApplication::create_graphics_pipeline(&device, extent, &render_pass);
-        let swapchain_framebuffers = HelloTriangleApplication::create_framebuffers(
-            &device,
-            &render_pass,
-            &frame_images,
-            extent,
-        );
-        let mut command_pool =
-            HelloTriangleApplication::create_command_pool(&device, queue_type, qf_id);
-        let submission_command_buffers = HelloTriangleApplication::create_command_buffers(
-            &mut command_pool,
-            &render_pass,
-            &swapchain_framebuffers,
-            extent,
-            &gfx_pipeline,
-        );
-        let (image_available_semaphores, render_finished_semaphores, in_flight_fences) =
-            HelloTriangleApplication::create_sync_objects(&device);
+    // the device is picked for the first window, the ones opened later have to be able to use it
+    unsafe fn init_hal(
+        window: Window,
+        modes: WindowModes,
+        settings: &mut Settings,
+    ) -> (HalState, WindowTarget) {
+        let instance = profile::scope("create_instance", || {
+            HelloTriangleApplication::create_instance()
+        });
+        let mut adapter = profile::scope("pick_adapter", || {
+            HelloTriangleApplication::pick_adapter(&instance)
+        });
+        let mut surface = profile::scope("create_surface", || {
+            HelloTriangleApplication::create_surface(&instance, &window)
+        });
+        let features = if settings.draw_queries {
+            DrawQueries::features(&adapter)
+        } else {
+            Features::empty()
+        };
+        let (device, mut command_queues, queue_type, qf_id) =
+            profile::scope("create_device", || {
+                HelloTriangleApplication::create_device_with_graphics_queues(
+                    &mut adapter,
+                    Some(&surface),
+                    features,
+                )
+            });
+        let device: DeviceRef = Rc::new(device);
+        let mut memory_allocator =
+            MemoryAllocator::new(&adapter, &device, memory::DEFAULT_BLOCK_SIZE);
+        let (swapchain, extent, backbuffer, format, swapchain_usage) =
+            profile::scope("create_swap_chain", || {
+                HelloTriangleApplication::create_swap_chain(
+                    &adapter,
+                    &device,
+                    &mut surface,
+                    HelloTriangleApplication::window_extent(&window),
+                    None,
+                )
+            });
+        let swapchain = resource::swapchain(&device, swapchain);
+        let frame_images = profile::scope("create_image_views", || {
+            HelloTriangleApplication::create_image_views(backbuffer, format, &device)
+        });
+        settings.validate_frames_in_flight(frame_images.len());
+        let mut frame_command_pools: Vec<_> = profile::scope("create_command_pools", || {
+            (0..settings.frames_in_flight)
+                .map(|_| HelloTriangleApplication::create_command_pool(&device, queue_type, qf_id))
+                .collect()
+        });
+        let first_window = profile::scope("create_window_target", || {
+            HelloTriangleApplication::create_window_target(
+                &device,
+                &mut memory_allocator,
+                &mut frame_command_pools,
+                window,
+                modes,
+                surface,
+                SwapchainImages {
+                    swapchain,
+                    frame_images,
+                    extent,
+                    format,
+                    usage: swapchain_usage,
+                },
+            )
+        });
+        let pipeline_cache = profile::scope("create_pipeline_cache", || {
+            HelloTriangleApplication::create_pipeline_cache(&adapter, &device)
+        });
+        let pipeline = profile::scope("create_graphics_pipeline", || {
+            HelloTriangleApplication::create_graphics_pipeline(
+                &device,
+                first_window
+                    .render_graph
+                    .render_pass(first_window.main_pass)
+                    .expect("main pass was culled!"),
+                &pipeline_cache,
+            )
+        });
+        let (upload_ring, descriptor_pool, frame_descriptor_set) =
+            profile::scope("create_descriptor_sets", || {
+                let upload_ring = UploadRing::new(
+                    &adapter,
+                    &device,
+                    &mut memory_allocator,
+                    settings.frames_in_flight,
+                    UPLOAD_FRAME_SIZE,
+                )
+                .expect("failed to create upload ring!");
+                let (descriptor_pool, frame_descriptor_set) =
+                    HelloTriangleApplication::create_frame_descriptor_set(
+                        &device,
+                        &pipeline,
+                        &upload_ring,
+                    );
+                (upload_ring, descriptor_pool, frame_descriptor_set)
+            });
+        let in_flight_fences = profile::scope("create_sync_objects", || {
+            HelloTriangleApplication::create_fences(&device, settings.frames_in_flight)
+        });
+        let (timestamp_queries, draw_queries) = profile::scope("create_query_pools", || {
+            let timestamp_queries = TimestampQueries::new(
+                &adapter,
+                &device,
+                &mut command_queues[0],
+                &mut frame_command_pools[0],
+                settings.frames_in_flight,
+            );
+            let draw_queries = if settings.draw_queries {
+                Some(DrawQueries::new(
+                    &device,
+                    features,
+                    settings.frames_in_flight,
+                    OBJECTS.len() as _,
+                ))
+            } else {
+                None
+            };
+            (timestamp_queries, draw_queries)
+        });
 
-        HalState {
+        let hal_state = HalState {
             in_flight_fences,
+            draw_statistics: Vec::new(),
+            draw_queries,
+            timestamp_queries,
+            frame_command_pools,
+            pipeline,
+            pipeline_cache,
+            frame_descriptor_set,
+            descriptor_pool,
+            upload_ring,
+            memory_allocator,
+            format,
+            command_queues,
+            queue_family: qf_id,
+            device,
+            adapter,
+            instance,
+        };
+        (hal_state, first_window)
+    }
+
+    // None if the window can't be presented to with the device and pipeline the first window
+    // picked, which only happens with windows on different gpus
+    unsafe fn open_window(
+        hal_state: &mut HalState,
+        events_loop: &EventsLoop,
+        mode: WindowMode,
+    ) -> Option<WindowTarget> {
+        let (window, modes) = HelloTriangleApplication::init_window(events_loop, mode);
+        let mut surface = HelloTriangleApplication::create_surface(&hal_state.instance, &window);
+        let queue_family = hal_state
+            .adapter
+            .queue_families
+            .iter()
+            .find(|family| family.id() == hal_state.queue_family)
+            .expect("queue family does not exist!");
+        if !surface.supports_queue_family(queue_family) {
+            warn!("could not open window, its surface does not support the graphics queue");
+            return None;
+        }
+
+        let (swapchain, extent, backbuffer, format, swapchain_usage) =
+            HelloTriangleApplication::create_swap_chain(
+                &hal_state.adapter,
+                &hal_state.device,
+                &mut surface,
+                HelloTriangleApplication::window_extent(&window),
+                None,
+            );
+        let swapchain = resource::swapchain(&hal_state.device, swapchain);
+        if format != hal_state.format {
+            warn!(
+                "could not open window, its surface wants {:?} instead of {:?}",
+                format, hal_state.format
+            );
+            return None;
+        }
+        let frame_images =
+            HelloTriangleApplication::create_image_views(backbuffer, format, &hal_state.device);
+
+        Some(HelloTriangleApplication::create_window_target(
+            &hal_state.device,
+            &mut hal_state.memory_allocator,
+            &mut hal_state.frame_command_pools,
+            window,
+            modes,
+            surface,
+            SwapchainImages {
+                swapchain,
+                frame_images,
+                extent,
+                format,
+                usage: swapchain_usage,
+            },
+        ))
+    }
+
+    unsafe fn create_window_target(
+        device: &DeviceRef,
+        memory_allocator: &mut MemoryAllocator,
+        frame_command_pools: &mut [resource::CommandPool],
+        window: Window,
+        modes: WindowModes,
+        surface: <back::Backend as Backend>::Surface,
+        swapchain: SwapchainImages,
+    ) -> WindowTarget {
+        let SwapchainImages {
+            swapchain,
+            frame_images,
+            extent,
+            format,
+            usage: swapchain_usage,
+        } = swapchain;
+        let frames_in_flight = frame_command_pools.len();
+        // frames in flight were picked for the first window's swapchain
+        if frame_images.len() < frames_in_flight {
+            warn!(
+                "window has {} swapchain images for {} frames in flight, frames will wait for its \
+                 images",
+                frame_images.len(),
+                frames_in_flight
+            );
+        }
+        let (compiled_graph, backbuffer, main_pass) =
+            HelloTriangleApplication::create_render_graph(format, true);
+        let mut render_graph =
+            RenderGraph::new(device, compiled_graph).expect("failed to create render graph!");
+        render_graph.resize(device, memory_allocator, extent, &frame_images);
+        let (image_available_semaphores, render_finished_semaphores) =
+            HelloTriangleApplication::create_semaphores(device, frames_in_flight);
+
+        WindowTarget {
+            images_in_flight: vec![None; frame_images.len()],
             render_finished_semaphores,
             image_available_semaphores,
-            submission_command_buffers,
-            command_pool,
-            swapchain_framebuffers,
-            gfx_pipeline,
-            descriptor_set_layouts,
-            pipeline_layout,
-            render_pass,
+            command_buffers: HelloTriangleApplication::create_command_buffers(frame_command_pools),
+            backbuffer,
+            main_pass,
+            render_graph,
             frame_images,
-            _format: format,
-            swapchain,
-            command_queues,
-            device,
-            _surface: surface,
-            _adapter: adapter,
-            _instance: instance,
+            screenshots: Screenshots::new(device, frames_in_flight, FrameOutput::Screenshots),
+            swapchain_usage,
+            extent,
+            replay_extent: None,
+            swapchain: Some(swapchain),
+            recreate_swapchain: false,
+            surface,
+            modes,
+            window,
+        }
+    }
+
+    // waits for the device, so only for when a window is closed
+    unsafe fn close_window(hal_state: &mut HalState, mut target: WindowTarget) {
+        hal_state
+            .device
+            .wait_idle()
+            .expect("Queues are not going idle!");
+        target.screenshots.finish(&mut hal_state.memory_allocator);
+        target.render_graph.release(&mut hal_state.memory_allocator);
+        for (command_pool, command_buffer) in hal_state
+            .frame_command_pools
+            .iter_mut()
+            .zip(target.command_buffers.drain(..))
+        {
+            command_pool.free(Some(command_buffer));
         }
     }
 
@@ -235,7 +734,9 @@ impl HelloTriangleApplication {
 
     fn create_device_with_graphics_queues(
         adapter: &mut Adapter<back::Backend>,
-        surface: &<back::Backend as Backend>::Surface,
+        // the queue has to be able to present to it, if there is one
+        surface: Option<&<back::Backend as Backend>::Surface>,
+        features: Features,
     ) -> (
         <back::Backend as Backend>::Device,
         Vec<queue::CommandQueue<back::Backend, Graphics>>,
@@ -248,7 +749,7 @@ impl HelloTriangleApplication {
             .find(|family| {
                 Graphics::supported_by(family.queue_type())
                     && family.max_queues() > 0
-                    && surface.supports_queue_family(family)
+                    && surface.map_or(true, |surface| surface.supports_queue_family(family))
             })
             .expect("Could not find a queue family supporting graphics.");
 
@@ -258,7 +759,7 @@ impl HelloTriangleApplication {
         let Gpu { device, mut queues } = unsafe {
             adapter
                 .physical_device
-                .open(&families, Features::empty())
+                .open(&families, features)
                 .expect("Could not create device.")
         };
 
@@ -275,12 +776,16 @@ impl HelloTriangleApplication {
         adapter: &Adapter<back::Backend>,
         device: &<back::Backend as Backend>::Device,
         surface: &mut <back::Backend as Backend>::Surface,
-        previous_swapchain: Option<<back::Backend as Backend>::Swapchain>,
+        // only used if the surface doesn't dictate its size
+        window_extent: window::Extent2D,
+        // replaced, and destroyed, by the new one
+        previous_swapchain: Option<resource::Swapchain>,
     ) -> (
         <back::Backend as Backend>::Swapchain,
         window::Extent2D,
         Backbuffer<back::Backend>,
         format::Format,
+        image::Usage,
     ) {
         let (caps, formats, _present_modes, _composite_alphas) =
             surface.compatibility(&adapter.physical_device);
@@ -293,25 +798,41 @@ impl HelloTriangleApplication {
                 .unwrap_or(formats[0])
         });
 
-        let swap_config = SwapchainConfig::from_caps(&caps, format, caps.extents.end);
+        let extent = window::Extent2D {
+            width: window_extent
+                .width
+                .max(caps.extents.start.width)
+                .min(caps.extents.end.width),
+            height: window_extent
+                .height
+                .max(caps.extents.start.height)
+                .min(caps.extents.end.height),
+        };
+        let mut swap_config = SwapchainConfig::from_caps(&caps, format, extent);
+        // screenshots copy out of the swapchain images
+        if caps.usage.contains(image::Usage::TRANSFER_SRC) {
+            swap_config.image_usage |= image::Usage::TRANSFER_SRC;
+        }
         let extent = swap_config.extent;
-        let (swapchain, backbuffer) = unsafe {
+        let usage = swap_config.image_usage;
+        let create = |previous_swapchain| unsafe {
             device
                 .create_swapchain(surface, swap_config, previous_swapchain)
                 .unwrap()
         };
+        let (swapchain, backbuffer) = match previous_swapchain {
+            Some(previous_swapchain) => previous_swapchain.consume(|raw| create(Some(raw))),
+            None => create(None),
+        };
 
-        (swapchain, extent, backbuffer, format)
+        (swapchain, extent, backbuffer, format, usage)
     }
 
     unsafe fn create_image_views(
         backbuffer: Backbuffer<back::Backend>,
         format: format::Format,
-        device: &<back::Backend as Backend>::Device,
-    ) -> Vec<(
-        <back::Backend as Backend>::Image,
-        <back::Backend as Backend>::ImageView,
-    )> {
+        device: &DeviceRef,
+    ) -> Vec<(<back::Backend as Backend>::Image, resource::ImageView)> {
         match backbuffer {
             window::Backbuffer::Images(images) => images
                 .into_iter()
@@ -331,441 +852,1162 @@ impl HelloTriangleApplication {
                         Err(_) => panic!("Error creating image view for an image!"),
                     };
 
-                    (image, image_view)
+                    (image, resource::image_view(device, image_view))
                 })
                 .collect(),
             _ => unimplemented!(),
         }
     }
 
-    fn create_render_pass(
-        device: &<back::Backend as Backend>::Device,
-        format: Option<format::Format>,
-    ) -> <back::Backend as Backend>::RenderPass {
-        let samples: u8 = 1;
-
-        let ops = pass::AttachmentOps {
-            load: pass::AttachmentLoadOp::Clear,
-            store: pass::AttachmentStoreOp::Store,
-        };
-
-        let stencil_ops = pass::AttachmentOps::DONT_CARE;
+    // stands in for a swapchain image when recording frames offscreen
+    unsafe fn create_offscreen_image(
+        device: &DeviceRef,
+        memory_allocator: &mut MemoryAllocator,
+        format: format::Format,
+        extent: window::Extent2D,
+    ) -> (resource::Image, resource::ImageView, Allocation) {
+        let mut image = resource::image(
+            device,
+            device
+                .create_image(
+                    image::Kind::D2(extent.width, extent.height, 1, 1),
+                    1,
+                    format,
+                    image::Tiling::Optimal,
+                    image::Usage::COLOR_ATTACHMENT | image::Usage::TRANSFER_SRC,
+                    image::ViewCapabilities::empty(),
+                )
+                .expect("failed to create offscreen image!"),
+        );
+        let requirements = device.get_image_requirements(&image);
+        let allocation = memory_allocator
+            .allocate(
+                requirements,
+                Properties::DEVICE_LOCAL,
+                Strategy::FreeList,
+                ResourceKind::Optimal,
+            )
+            .expect("failed to allocate offscreen image memory!");
+        device
+            .bind_image_memory(allocation.memory(), allocation.offset(), &mut image)
+            .expect("failed to bind offscreen image memory!");
+        let image_view = device
+            .create_image_view(
+                &image,
+                image::ViewKind::D2,
+                format,
+                format::Swizzle::NO,
+                image::SubresourceRange {
+                    aspects: format::Aspects::COLOR,
+                    levels: 0..1,
+                    layers: 0..1,
+                },
+            )
+            .expect("failed to create offscreen image view!");
 
-        let layouts = image::Layout::Undefined..image::Layout::Present;
+        (image, resource::image_view(device, image_view), allocation)
+    }
 
-        let color_attachment = pass::Attachment {
-            format,
-            samples,
-            ops,
-            stencil_ops,
-            layouts,
+    // the whole frame as a render graph, compiled without touching the device; a backbuffer that
+    // isn't presented is left ready to be copied from instead
+    fn create_render_graph(
+        format: format::Format,
+        presented: bool,
+    ) -> (CompiledGraph, ResourceId, PassId) {
+        let mut render_graph = RenderGraphBuilder::new();
+        let backbuffer = if presented {
+            render_graph.backbuffer("backbuffer", format)
+        } else {
+            render_graph.offscreen_backbuffer("backbuffer", format)
         };
+        let main_pass = render_graph.pass(PassBuilder::new("main").clear_color(backbuffer));
 
-        let color_attachment_ref: pass::AttachmentRef = (0, image::Layout::ColorAttachmentOptimal);
-
-        // hal assumes pipeline bind point is GRAPHICS
-        let subpass = pass::SubpassDesc {
-            colors: &[color_attachment_ref],
-            depth_stencil: None,
-            inputs: &[],
-            resolves: &[],
-            preserves: &[],
-        };
+        let compiled = render_graph
+            .compile()
+            .expect("failed to compile render graph!");
+        (compiled, backbuffer, main_pass)
+    }
 
-        unsafe {
-            device
-                .create_render_pass(&[color_attachment], &[subpass], &[])
-                .unwrap()
-        }
+    fn pipeline_cache_path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|cache_dir| {
+            cache_dir
+                .join("gfx-hal-tutorial")
+                .join(format!("{}.pipeline_cache", WINDOW_NAME))
+        })
     }
 
-    unsafe fn create_graphics_pipeline(
-        device: &<back::Backend as Backend>::Device,
-        extent: window::Extent2D,
-        render_pass: &<back::Backend as Backend>::RenderPass,
-    ) -> (
-        Vec<<back::Backend as Backend>::DescriptorSetLayout>,
-        <back::Backend as Backend>::PipelineLayout,
-        <back::Backend as Backend>::GraphicsPipeline,
-    ) {
-        let vert_shader_code = glsl_to_spirv::compile(
-            include_str!("09_shader_base.vert"),
-            glsl_to_spirv::ShaderType::Vertex,
-        )
-        .expect("Error compiling vertex shader code.")
-        .bytes()
-        .map(|b| b.unwrap())
-        .collect::<Vec<u8>>();
+    fn pipeline_cache_header(adapter: &Adapter<back::Backend>) -> Vec<u8> {
+        let mut header = Vec::with_capacity(PIPELINE_CACHE_HEADER_SIZE);
+        header.extend_from_slice(&PIPELINE_CACHE_MAGIC);
+        header.extend_from_slice(&(adapter.info.vendor as u64).to_le_bytes());
+        header.extend_from_slice(&(adapter.info.device as u64).to_le_bytes());
+        header
+    }
 
-        let frag_shader_code = glsl_to_spirv::compile(
-            include_str!("09_shader_base.frag"),
-            glsl_to_spirv::ShaderType::Fragment,
-        )
-        .expect("Error compiling fragment shader code.")
-        .bytes()
-        .map(|b| b.unwrap())
-        .collect::<Vec<u8>>();
-
-        let vert_shader_module = device
-            .create_shader_module(&vert_shader_code)
-            .expect("Error creating shader module.");
-        let frag_shader_module = device
-            .create_shader_module(&frag_shader_code)
-            .expect("Error creating fragment module.");
-
-        let (descriptor_set_layouts, pipeline_layout, gfx_pipeline) = {
-            let (vs_entry, fs_entry) = (
-                pso::EntryPoint::<back::Backend> {
-                    entry: "main",
-                    module: &vert_shader_module,
-                    specialization: hal::pso::Specialization {
-                        constants: &[],
-                        data: &[],
-                    },
-                },
-                pso::EntryPoint::<back::Backend> {
-                    entry: "main",
-                    module: &frag_shader_module,
-                    specialization: hal::pso::Specialization {
-                        constants: &[],
-                        data: &[],
-                    },
-                },
-            );
+    fn load_pipeline_cache_data(adapter: &Adapter<back::Backend>) -> Option<Vec<u8>> {
+        let path = HelloTriangleApplication::pipeline_cache_path()?;
+        let mut data = fs::read(&path).ok()?;
+
+        // a cache written for another adapter (or driver install) is useless at best
+        if data.len() < PIPELINE_CACHE_HEADER_SIZE
+            || data[..PIPELINE_CACHE_HEADER_SIZE]
+                != HelloTriangleApplication::pipeline_cache_header(adapter)[..]
+        {
+            info!("discarding stale pipeline cache at {}", path.display());
+            return None;
+        }
 
-            let shaders = pso::GraphicsShaderSet {
-                vertex: vs_entry,
-                hull: None,
-                domain: None,
-                geometry: None,
-                fragment: Some(fs_entry),
-            };
+        Some(data.split_off(PIPELINE_CACHE_HEADER_SIZE))
+    }
 
-            let rasterizer = pso::Rasterizer {
-                depth_clamping: false,
-                polygon_mode: pso::PolygonMode::Fill,
-                cull_face: <pso::Face>::BACK,
-                front_face: pso::FrontFace::Clockwise,
-                depth_bias: None,
-                conservative: false,
+    unsafe fn create_pipeline_cache(
+        adapter: &Adapter<back::Backend>,
+        device: &DeviceRef,
+    ) -> resource::PipelineCache {
+        let data = HelloTriangleApplication::load_pipeline_cache_data(adapter);
+
+        let pipeline_cache =
+            match device.create_pipeline_cache(data.as_ref().map(|data| data.as_slice())) {
+                Ok(pipeline_cache) => pipeline_cache,
+                // the driver may still reject data that passed our header check, so start empty
+                Err(_) => device
+                    .create_pipeline_cache(None)
+                    .expect("failed to create pipeline cache!"),
             };
 
-            let vertex_buffers: Vec<pso::VertexBufferDesc> = Vec::new();
-            let attributes: Vec<pso::AttributeDesc> = Vec::new();
-
-            let input_assembler = pso::InputAssemblerDesc::new(Primitive::TriangleList);
-
-            let blender = {
-                let blend_state = pso::BlendState::On {
-                    color: pso::BlendOp::Add {
-                        src: pso::Factor::One,
-                        dst: pso::Factor::Zero,
-                    },
-                    alpha: pso::BlendOp::Add {
-                        src: pso::Factor::One,
-                        dst: pso::Factor::Zero,
-                    },
-                };
-
-                pso::BlendDesc {
-                    logic_op: Some(pso::LogicOp::Copy),
-                    targets: vec![pso::ColorBlendDesc(pso::ColorMask::ALL, blend_state)],
-                }
-            };
+        resource::pipeline_cache(device, pipeline_cache)
+    }
 
-            let depth_stencil = pso::DepthStencilDesc {
-                depth: pso::DepthTest::Off,
-                depth_bounds: false,
-                stencil: pso::StencilTest::Off,
-            };
+    unsafe fn save_pipeline_cache(
+        adapter: &Adapter<back::Backend>,
+        device: &<back::Backend as Backend>::Device,
+        pipeline_cache: &<back::Backend as Backend>::PipelineCache,
+    ) {
+        let path = match HelloTriangleApplication::pipeline_cache_path() {
+            Some(path) => path,
+            None => return,
+        };
 
-            let multisampling: Option<pso::Multisampling> = None;
-
-            let baked_states = pso::BakedStates {
-                viewport: Some(pso::Viewport {
-                    rect: pso::Rect {
-                        x: 0,
-                        y: 0,
-                        w: extent.width as i16,
-                        h: extent.height as i16,
-                    },
-                    depth: (0.0..1.0),
-                }),
-                scissor: Some(pso::Rect {
-                    x: 0,
-                    y: 0,
-                    w: extent.width as i16,
-                    h: extent.height as i16,
-                }),
-                blend_color: None,
-                depth_bounds: None,
-            };
+        let data = match device.get_pipeline_cache_data(pipeline_cache) {
+            Ok(data) => data,
+            Err(_) => {
+                warn!("could not retrieve pipeline cache data");
+                return;
+            }
+        };
 
-            let bindings = Vec::<pso::DescriptorSetLayoutBinding>::new();
-            let immutable_samplers = Vec::<<back::Backend as Backend>::Sampler>::new();
-            let descriptor_set_layouts: Vec<<back::Backend as Backend>::DescriptorSetLayout> =
-                vec![device
-                    .create_descriptor_set_layout(bindings, immutable_samplers)
-                    .unwrap()];
-            let push_constants = Vec::<(pso::ShaderStageFlags, std::ops::Range<u32>)>::new();
-            let layout = device
-                .create_pipeline_layout(&descriptor_set_layouts, push_constants)
-                .unwrap();
+        let mut contents = HelloTriangleApplication::pipeline_cache_header(adapter);
+        contents.extend_from_slice(&data);
 
-            let subpass = pass::Subpass {
-                index: 0,
-                main_pass: render_pass,
-            };
+        let result = match path.parent() {
+            Some(parent) => fs::create_dir_all(parent),
+            None => Ok(()),
+        }
+        .and_then(|_| fs::write(&path, &contents));
 
-            let flags = pso::PipelineCreationFlags::empty();
-
-            let parent = pso::BasePipeline::None;
-
-            let gfx_pipeline = {
-                let desc = pso::GraphicsPipelineDesc {
-                    shaders,
-                    rasterizer,
-                    vertex_buffers,
-                    attributes,
-                    input_assembler,
-                    blender,
-                    depth_stencil,
-                    multisampling,
-                    baked_states,
-                    layout: &layout,
-                    subpass,
-                    flags,
-                    parent,
-                };
+        if let Err(err) = result {
+            warn!(
+                "could not write pipeline cache to {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
 
-                device
-                    .create_graphics_pipeline(&desc, None)
-                    .expect("failed to create graphics pipeline!")
-            };
+    unsafe fn create_graphics_pipeline(
+        device: &DeviceRef,
+        render_pass: &<back::Backend as Backend>::RenderPass,
+        pipeline_cache: &<back::Backend as Backend>::PipelineCache,
+    ) -> Pipeline {
+        let vert_shader_code = pipeline::compile_shader(
+            include_str!("15_shader_base.vert"),
+            glsl_to_spirv::ShaderType::Vertex,
+        );
+        let frag_shader_code = pipeline::compile_shader(
+            include_str!("09_shader_base.frag"),
+            glsl_to_spirv::ShaderType::Fragment,
+        );
 
-            (descriptor_set_layouts, layout, gfx_pipeline)
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
         };
 
-        device.destroy_shader_module(vert_shader_module);
-        device.destroy_shader_module(frag_shader_module);
+        // viewport and scissor are left dynamic so resizing doesn't invalidate the pipeline
+        PipelineBuilder::new(&vert_shader_code, &frag_shader_code)
+            .descriptor_binding(pso::DescriptorSetLayoutBinding {
+                binding: 0,
+                ty: pso::DescriptorType::UniformBufferDynamic,
+                count: 1,
+                stage_flags: pso::ShaderStageFlags::VERTEX,
+                immutable_samplers: false,
+            })
+            .push_constants(pso::ShaderStageFlags::VERTEX, 0..3)
+            .build(device, subpass, Some(pipeline_cache))
+    }
 
-        (descriptor_set_layouts, pipeline_layout, gfx_pipeline)
+    unsafe fn create_frame_descriptor_set(
+        device: &DeviceRef,
+        pipeline: &Pipeline,
+        upload_ring: &UploadRing,
+    ) -> (
+        resource::DescriptorPool,
+        <back::Backend as Backend>::DescriptorSet,
+    ) {
+        let mut descriptor_pool = resource::descriptor_pool(
+            device,
+            device
+                .create_descriptor_pool(
+                    1,
+                    &[pso::DescriptorRangeDesc {
+                        ty: pso::DescriptorType::UniformBufferDynamic,
+                        count: 1,
+                    }],
+                )
+                .expect("failed to create descriptor pool!"),
+        );
+        let descriptor_set = descriptor_pool
+            .allocate_set(&pipeline.descriptor_set_layouts[0])
+            .expect("failed to allocate descriptor set!");
+
+        // the range covers one frame's uniforms, where they are in the ring is given when binding
+        device.write_descriptor_sets(vec![pso::DescriptorSetWrite {
+            set: &descriptor_set,
+            binding: 0,
+            array_offset: 0,
+            descriptors: Some(pso::Descriptor::Buffer(
+                upload_ring.buffer(),
+                None..Some(mem::size_of::<FrameUniforms>() as u64),
+            )),
+        }]);
+
+        (descriptor_pool, descriptor_set)
     }
 
-    fn create_framebuffers(
-        device: &<back::Backend as Backend>::Device,
-        render_pass: &<back::Backend as Backend>::RenderPass,
-        frame_images: &[(
-            <back::Backend as Backend>::Image,
-            <back::Backend as Backend>::ImageView,
-        )],
-        extent: window::Extent2D,
-    ) -> Vec<<back::Backend as Backend>::Framebuffer> {
-        let mut swapchain_framebuffers: Vec<<back::Backend as Backend>::Framebuffer> = Vec::new();
+    unsafe fn create_command_buffers(
+        command_pools: &mut [resource::CommandPool],
+    ) -> Vec<FrameCommandBuffer> {
+        command_pools
+            .iter_mut()
+            .map(|command_pool| command_pool.acquire_command_buffer())
+            .collect()
+    }
 
-        unsafe {
-            for (_, image_view) in frame_images.iter() {
-                swapchain_framebuffers.push(
-                    device
-                        .create_framebuffer(
-                            render_pass,
-                            vec![image_view],
-                            image::Extent {
-                                width: extent.width as _,
-                                height: extent.height as _,
-                                depth: 1,
-                            },
-                        )
-                        .expect("failed to create framebuffer!"),
-                );
-            }
+    unsafe fn record_command_buffer(
+        command_buffer: &mut FrameCommandBuffer,
+        context: &FrameContext,
+        target: DrawTarget,
+        frame_uniforms_offset: u32,
+        mut timestamp_queries: Option<&mut TimestampQueries>,
+        mut draw_queries: Option<&mut DrawQueries>,
+    ) {
+        let FrameContext {
+            frame,
+            pipeline,
+            frame_descriptor_set,
+            scene,
+        } = *context;
+        let DrawTarget {
+            render_graph,
+            backbuffer,
+            main_pass,
+            image_index,
+            image,
+            extent,
+            screenshots,
+        } = target;
+        let render_area = pso::Rect {
+            x: 0,
+            y: 0,
+            w: extent.width as _,
+            h: extent.height as _,
+        };
+
+        command_buffer.begin();
+        command_buffer.bind_graphics_pipeline(&pipeline.pipeline);
+        command_buffer.bind_graphics_descriptor_sets(
+            &pipeline.layout,
+            0,
+            Some(frame_descriptor_set),
+            &[frame_uniforms_offset],
+        );
+        command_buffer.set_viewports(
+            0,
+            &[pso::Viewport {
+                rect: render_area,
+                depth: (0.0..1.0),
+            }],
+        );
+        command_buffer.set_scissors(0, &[render_area]);
+
+        if let Some(ref mut timestamp_queries) = timestamp_queries {
+            timestamp_queries.begin(command_buffer, frame);
         }
 
-        swapchain_framebuffers
-    }
+        if let Some(ref mut draw_queries) = draw_queries {
+            draw_queries.reset(command_buffer, frame);
+        }
 
-    unsafe fn create_command_buffers<'a>(
-        command_pool: &'a mut pool::CommandPool<back::Backend, Graphics>,
-        render_pass: &<back::Backend as Backend>::RenderPass,
-        framebuffers: &[<back::Backend as Backend>::Framebuffer],
-        extent: window::Extent2D,
-        pipeline: &<back::Backend as Backend>::GraphicsPipeline,
-    ) -> Vec<command::CommandBuffer<back::Backend, Graphics, command::MultiShot, command::Primary>>
-    {
-        let mut submission_command_buffers: Vec<
-            command::CommandBuffer<back::Backend, Graphics, command::MultiShot, command::Primary>,
-        > = Vec::new();
-
-        for fb in framebuffers.iter() {
-            let mut command_buffer: command::CommandBuffer<
-                back::Backend,
-                Graphics,
-                command::MultiShot,
-                command::Primary,
-            > = command_pool.acquire_command_buffer();
-
-            command_buffer.begin(true);
-            command_buffer.bind_graphics_pipeline(pipeline);
-            {
-                // begin render pass
-                let render_area = pso::Rect {
-                    x: 0,
-                    y: 0,
-                    w: extent.width as _,
-                    h: extent.height as _,
-                };
-                let clear_values = vec![command::ClearValue::Color(command::ClearColor::Float([
-                    0.0, 0.0, 0.0, 1.0,
-                ]))];
-
-                let mut render_pass_inline_encoder = command_buffer.begin_render_pass_inline(
-                    render_pass,
-                    fb,
-                    render_area,
-                    clear_values.iter(),
+        render_graph.set_clear_value(
+            backbuffer,
+            command::ClearValue::Color(command::ClearColor::Float(scene.clear_color())),
+        );
+        render_graph.record(command_buffer, image_index, image, |pass, encoder, _| {
+            debug_assert_eq!(pass, main_pass);
+
+            for object in OBJECTS.iter() {
+                let constants: Vec<u32> = object.iter().map(|value| value.to_bits()).collect();
+                encoder.push_graphics_constants(
+                    &pipeline.layout,
+                    pso::ShaderStageFlags::VERTEX,
+                    0,
+                    &constants,
                 );
 
-                render_pass_inline_encoder.draw(0..3, 0..1);
+                match draw_queries {
+                    Some(ref mut draw_queries) => {
+                        draw_queries.query_draw(encoder, frame, |encoder| encoder.draw(0..3, 0..1))
+                    }
+                    None => encoder.draw(0..3, 0..1),
+                }
             }
-            command_buffer.finish();
+        });
 
-            submission_command_buffers.push(command_buffer);
+        if let Some(timestamp_queries) = timestamp_queries {
+            timestamp_queries.end(command_buffer, frame);
         }
 
-        submission_command_buffers
+        screenshots.record_copy(command_buffer, frame, render_graph, image_index, image);
+
+        command_buffer.finish();
     }
 
     unsafe fn create_command_pool(
-        device: &<back::Backend as Backend>::Device,
+        device: &DeviceRef,
         queue_type: queue::QueueType,
         qf_id: queue::family::QueueFamilyId,
-    ) -> pool::CommandPool<back::Backend, Graphics> {
+    ) -> resource::CommandPool {
         let raw_command_pool = device
-            .create_command_pool(qf_id, pool::CommandPoolCreateFlags::empty())
+            .create_command_pool(qf_id, pool::CommandPoolCreateFlags::TRANSIENT)
             .unwrap();
 
         // safety check necessary before creating a strongly typed command pool
         assert_eq!(Graphics::supported_by(queue_type), true);
-        pool::CommandPool::new(raw_command_pool)
+        resource::command_pool(device, pool::CommandPool::new(raw_command_pool))
     }
 
+    // draws the scene into every window that isn't minimized, with a single submission; windows
+    // whose swapchain no longer matches their surface are recreated first; returns false if no
+    // window could be drawn to
     unsafe fn draw_frame(
-        device: &<back::Backend as Backend>::Device,
-        command_queues: &mut [queue::CommandQueue<back::Backend, Graphics>],
-        swapchain: &mut <back::Backend as Backend>::Swapchain,
-        submission_command_buffers: &[command::CommandBuffer<
-            back::Backend,
-            Graphics,
-            command::MultiShot,
-            command::Primary,
-        >],
-        image_available_semaphore: &<back::Backend as Backend>::Semaphore,
-        render_finished_semaphore: &<back::Backend as Backend>::Semaphore,
-        in_flight_fence: &<back::Backend as Backend>::Fence,
-    ) {
-        device
-            .wait_for_fence(in_flight_fence, std::u64::MAX)
+        hal_state: &mut HalState,
+        windows: &mut [WindowTarget],
+        current_frame: usize,
+        // already interpolated between the last two simulation steps
+        scene: &Scene,
+        screenshot_window: Option<WindowId>,
+        timing: &mut FrameTiming,
+    ) -> bool {
+        let fence_wait_start = Instant::now();
+        {
+            let _span = profile::span("wait_for_fence");
+            hal_state
+                .device
+                .wait_for_fence(&hal_state.in_flight_fences[current_frame], std::u64::MAX)
+                .unwrap();
+        }
+        timing.fence_wait += fence_wait_start.elapsed();
+
+        if let Some(ref mut timestamp_queries) = hal_state.timestamp_queries {
+            let render_pass = timestamp_queries.read(&hal_state.device, current_frame);
+            if let Some((start, duration)) = render_pass {
+                profile::gpu_span("render_pass", start, duration);
+            }
+            timing.gpu_render_pass = render_pass.map(|(_, duration)| duration);
+        }
+
+        if let Some(ref mut draw_queries) = hal_state.draw_queries {
+            if let Some(draw_statistics) = draw_queries.read(&hal_state.device, current_frame) {
+                hal_state.draw_statistics = draw_statistics;
+            }
+        }
+
+        for target in windows.iter_mut() {
+            target
+                .screenshots
+                .finish_frame(&mut hal_state.memory_allocator, current_frame);
+        }
+
+        // the windows drawn to this frame and the swapchain image each of them acquired
+        let mut acquired: Vec<(usize, usize)> = Vec::new();
+        for (window_index, target) in windows.iter_mut().enumerate() {
+            // a minimized window has no area to present to
+            let window_extent = HelloTriangleApplication::window_extent(&target.window);
+            if window_extent.width == 0 || window_extent.height == 0 {
+                continue;
+            }
+            if target.recreate_swapchain {
+                HelloTriangleApplication::recreate_swap_chain(hal_state, target, window_extent);
+                target.recreate_swapchain = false;
+            }
+            if target.swapchain.is_none() {
+                continue;
+            }
+
+            let acquire_start = Instant::now();
+            let image = {
+                let _span = profile::span("acquire_image");
+                target
+                    .swapchain
+                    .as_mut()
+                    .expect("swapchain does not exist!")
+                    .acquire_image(
+                        std::u64::MAX,
+                        window::FrameSync::Semaphore(
+                            &target.image_available_semaphores[current_frame],
+                        ),
+                    )
+            };
+            timing.acquire_wait += acquire_start.elapsed();
+            let image_index = match image {
+                Ok(image_index) => image_index as usize,
+                Err(_) => {
+                    target.recreate_swapchain = true;
+                    continue;
+                }
+            };
+
+            // with more swapchain images than frames in flight (or images acquired out of
+            // order), the image may still be in use by another frame that has its own fence
+            let image_in_flight = &mut target.images_in_flight[image_index];
+            if let Some(frame) = *image_in_flight {
+                if frame != current_frame {
+                    let _span = profile::span("wait_for_image_fence");
+                    let fence_wait_start = Instant::now();
+                    hal_state
+                        .device
+                        .wait_for_fence(&hal_state.in_flight_fences[frame], std::u64::MAX)
+                        .unwrap();
+                    timing.fence_wait += fence_wait_start.elapsed();
+                }
+            }
+            *image_in_flight = Some(current_frame);
+            acquired.push((window_index, image_index));
+        }
+
+        // the fence is only reset once we know work will be submitted, otherwise the next wait
+        // on it would never return
+        if acquired.is_empty() {
+            return false;
+        }
+        hal_state
+            .device
+            .reset_fence(&hal_state.in_flight_fences[current_frame])
             .unwrap();
-        device.reset_fence(in_flight_fence).unwrap();
 
-        let image_index = swapchain
-            .acquire_image(
-                std::u64::MAX,
-                window::FrameSync::Semaphore(image_available_semaphore),
-            )
-            .expect("could not acquire image!");
+        // the fence signalled, so the gpu is done with everything recorded from this pool
+        hal_state.frame_command_pools[current_frame].reset();
+        hal_state.upload_ring.begin_frame(current_frame);
+
+        let context = FrameContext {
+            frame: current_frame,
+            pipeline: &hal_state.pipeline,
+            frame_descriptor_set: &hal_state.frame_descriptor_set,
+            scene,
+        };
+        let record_span = profile::span("record_command_buffer");
+        // only the first window drawn is timed and counted
+        let mut timestamp_queries = hal_state.timestamp_queries.as_mut();
+        let mut draw_queries = hal_state.draw_queries.as_mut();
+        for &(window_index, image_index) in acquired.iter() {
+            let target = &mut windows[window_index];
+            let aspect_extent = target.replay_extent.unwrap_or(target.extent);
+            let aspect = aspect_extent.width as f32 / aspect_extent.height as f32;
+            let frame_uniforms_offset = hal_state
+                .upload_ring
+                .upload(&[scene.frame_uniforms(aspect)])
+                .expect("upload ring is full!");
+
+            if screenshot_window == Some(target.window.id()) {
+                if let Err(err) = target.screenshots.capture(
+                    &mut hal_state.memory_allocator,
+                    current_frame,
+                    hal_state.format,
+                    target.swapchain_usage,
+                    target.extent,
+                ) {
+                    warn!("could not capture screenshot: {}", err);
+                }
+            }
+
+            HelloTriangleApplication::record_command_buffer(
+                &mut target.command_buffers[current_frame],
+                &context,
+                DrawTarget {
+                    render_graph: &mut target.render_graph,
+                    backbuffer: target.backbuffer,
+                    main_pass: target.main_pass,
+                    image_index,
+                    image: &target.frame_images[image_index].0,
+                    extent: target.extent,
+                    screenshots: &target.screenshots,
+                },
+                frame_uniforms_offset as u32,
+                timestamp_queries.take(),
+                draw_queries.take(),
+            );
+        }
+        hal_state.upload_ring.end_frame(current_frame);
+        drop(record_span);
 
-        let i = image_index as usize;
         let submission = queue::Submission {
-            command_buffers: &submission_command_buffers[i..i + 1],
-            wait_semaphores: vec![(
-                image_available_semaphore,
-                pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
-            )],
-            signal_semaphores: vec![render_finished_semaphore],
+            command_buffers: acquired
+                .iter()
+                .map(|&(window_index, _)| &windows[window_index].command_buffers[current_frame])
+                .collect::<Vec<_>>(),
+            wait_semaphores: acquired
+                .iter()
+                .map(|&(window_index, _)| {
+                    (
+                        &*windows[window_index].image_available_semaphores[current_frame],
+                        pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+                    )
+                })
+                .collect::<Vec<_>>(),
+            signal_semaphores: acquired
+                .iter()
+                .map(|&(window_index, _)| {
+                    &*windows[window_index].render_finished_semaphores[current_frame]
+                })
+                .collect::<Vec<_>>(),
         };
 
         // recall we only made one queue
-        command_queues[0].submit(submission, Some(in_flight_fence));
+        {
+            let _span = profile::span("submit");
+            hal_state.command_queues[0].submit(
+                submission,
+                Some(&*hal_state.in_flight_fences[current_frame]),
+            );
+        }
 
-        swapchain
-            .present(
-                &mut command_queues[0],
-                image_index,
-                vec![render_finished_semaphore],
-            )
-            .expect("presentation failed!");
+        let _span = profile::span("present");
+        for &(window_index, image_index) in acquired.iter() {
+            let target = &mut windows[window_index];
+            let presented = target
+                .swapchain
+                .as_mut()
+                .expect("swapchain does not exist!")
+                .present(
+                    &mut hal_state.command_queues[0],
+                    image_index as window::SwapImageIndex,
+                    vec![&*target.render_finished_semaphores[current_frame]],
+                );
+            if presented.is_err() {
+                target.recreate_swapchain = true;
+            }
+        }
+        true
     }
 
-    fn create_sync_objects(
-        device: &<back::Backend as Backend>::Device,
-    ) -> (
-        Vec<<back::Backend as Backend>::Semaphore>,
-        Vec<<back::Backend as Backend>::Semaphore>,
-        Vec<<back::Backend as Backend>::Fence>,
+    // the pipeline uses dynamic viewport and scissor state and command buffers are recorded
+    // every frame, so only objects that depend on the swapchain images have to be rebuilt; unless
+    // the surface now wants another format (e.g. after moving to an hdr monitor), which the
+    // pipeline shared by every window isn't compatible with, in which case the window is left
+    // without a swapchain
+    unsafe fn recreate_swap_chain(
+        hal_state: &mut HalState,
+        target: &mut WindowTarget,
+        window_extent: window::Extent2D,
     ) {
-        let mut image_available_semaphores: Vec<<back::Backend as Backend>::Semaphore> = Vec::new();
-        let mut render_finished_semaphores: Vec<<back::Backend as Backend>::Semaphore> = Vec::new();
-        let mut in_flight_fences: Vec<<back::Backend as Backend>::Fence> = Vec::new();
+        let _span = profile::span("recreate_swap_chain");
+        let device = &hal_state.device;
+
+        device.wait_idle().expect("Queues are not going idle!");
 
-        for _ in 0..MAX_FRAMES_IN_FLIGHT {
-            image_available_semaphores.push(device.create_semaphore().unwrap());
-            render_finished_semaphores.push(device.create_semaphore().unwrap());
-            in_flight_fences.push(device.create_fence(true).unwrap());
+        target.render_graph.release(&mut hal_state.memory_allocator);
+        target.frame_images.clear();
+
+        let (swapchain, extent, backbuffer, format, swapchain_usage) =
+            HelloTriangleApplication::create_swap_chain(
+                &hal_state.adapter,
+                device,
+                &mut target.surface,
+                window_extent,
+                target.swapchain.take(),
+            );
+        let swapchain = resource::swapchain(device, swapchain);
+        if format != hal_state.format {
+            warn!(
+                "closing window, its surface changed from {:?} to {:?}",
+                hal_state.format, format
+            );
+            return;
         }
 
-        (
-            image_available_semaphores,
-            render_finished_semaphores,
-            in_flight_fences,
+        target.frame_images =
+            HelloTriangleApplication::create_image_views(backbuffer, format, device);
+        // the device is idle, so no image is in use by any frame
+        target.images_in_flight = vec![None; target.frame_images.len()];
+        target.render_graph.resize(
+            device,
+            &mut hal_state.memory_allocator,
+            extent,
+            &target.frame_images,
+        );
+        target.swapchain = Some(swapchain);
+        target.extent = extent;
+        target.swapchain_usage = swapchain_usage;
+    }
+
+    // one of each per frame in flight, for a window
+    fn create_semaphores(
+        device: &DeviceRef,
+        frames_in_flight: usize,
+    ) -> (Vec<resource::Semaphore>, Vec<resource::Semaphore>) {
+        let mut image_available_semaphores: Vec<resource::Semaphore> = Vec::new();
+        let mut render_finished_semaphores: Vec<resource::Semaphore> = Vec::new();
+
+        for _ in 0..frames_in_flight {
+            image_available_semaphores.push(resource::semaphore(
+                device,
+                device.create_semaphore().unwrap(),
+            ));
+            render_finished_semaphores.push(resource::semaphore(
+                device,
+                device.create_semaphore().unwrap(),
+            ));
+        }
+
+        (image_available_semaphores, render_finished_semaphores)
+    }
+
+    // signalled, so the first wait on each returns at once
+    fn create_fences(device: &DeviceRef, frames_in_flight: usize) -> Vec<resource::Fence> {
+        (0..frames_in_flight)
+            .map(|_| resource::fence(device, device.create_fence(true).unwrap()))
+            .collect()
+    }
+
+    fn record_output(settings: &Settings) -> FrameOutput {
+        let path = settings
+            .record_to
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("frames"));
+        if path
+            .extension()
+            .map_or(false, |extension| extension == "y4m")
+        {
+            // y4m wants the rate as a fraction, milliframes keep rates like 29.97 exact enough
+            return FrameOutput::Y4m {
+                path,
+                frame_rate: ((settings.record_rate * 1000.0).round() as u32, 1000),
+            };
+        }
+
+        if let Err(err) = fs::create_dir_all(&path) {
+            warn!("could not create {}: {}", path.display(), err);
+        }
+        FrameOutput::PngSequence(path)
+    }
+
+    // renders `record_frames` frames without a window, each one advancing the simulation by
+    // exactly one frame at the recording rate, so the result doesn't depend on how fast the
+    // frames can be rendered and written
+    //
+    // with an input replay every frame takes its events and elapsed time from the replay instead,
+    // reproducing the frames of the recorded run, and recording stops when the replay ends
+    unsafe fn record_offscreen(settings: &Settings) {
+        HelloTriangleApplication::render_offscreen(settings);
+
+        // everything created for the recording is gone after this, anything still alive was
+        // never dropped
+        let leaked = leak::report();
+        if leaked > 0 {
+            warn!("{} device objects were not destroyed", leaked);
+        }
+    }
+
+    unsafe fn render_offscreen(settings: &Settings) {
+        let frame_count = settings.record_frames.unwrap_or(0);
+        let format = format::Format::Rgba8Srgb;
+        let extent = RECORD_EXTENT;
+
+        let instance = HelloTriangleApplication::create_instance();
+        let mut adapter = HelloTriangleApplication::pick_adapter(&instance);
+        let (device, mut command_queues, queue_type, qf_id) =
+            HelloTriangleApplication::create_device_with_graphics_queues(
+                &mut adapter,
+                None,
+                Features::empty(),
+            );
+        let device: DeviceRef = Rc::new(device);
+        let mut memory_allocator =
+            MemoryAllocator::new(&adapter, &device, memory::DEFAULT_BLOCK_SIZE);
+        let (image, image_view, image_allocation) =
+            HelloTriangleApplication::create_offscreen_image(
+                &device,
+                &mut memory_allocator,
+                format,
+                extent,
+            );
+        let frame_images = vec![(image, image_view)];
+
+        let (compiled_graph, backbuffer, main_pass) =
+            HelloTriangleApplication::create_render_graph(format, false);
+        let mut render_graph =
+            RenderGraph::new(&device, compiled_graph).expect("failed to create render graph!");
+        let pipeline_cache = HelloTriangleApplication::create_pipeline_cache(&adapter, &device);
+        let pipeline = HelloTriangleApplication::create_graphics_pipeline(
+            &device,
+            render_graph
+                .render_pass(main_pass)
+                .expect("main pass was culled!"),
+            &pipeline_cache,
+        );
+        let mut screenshots = Screenshots::new(
+            &device,
+            1,
+            HelloTriangleApplication::record_output(settings),
+        );
+        let mut upload_ring = UploadRing::new(
+            &adapter,
+            &device,
+            &mut memory_allocator,
+            1,
+            UPLOAD_FRAME_SIZE,
         )
+        .expect("failed to create upload ring!");
+        let (_descriptor_pool, frame_descriptor_set) =
+            HelloTriangleApplication::create_frame_descriptor_set(&device, &pipeline, &upload_ring);
+        render_graph.resize(&device, &mut memory_allocator, extent, &frame_images);
+        let mut command_pools = vec![HelloTriangleApplication::create_command_pool(
+            &device, queue_type, qf_id,
+        )];
+        let mut command_buffers =
+            HelloTriangleApplication::create_command_buffers(&mut command_pools);
+        let fences = HelloTriangleApplication::create_fences(&device, 1);
+
+        let mut timestep = FixedTimestep::from_rate(settings.update_rate);
+        let frame_time = timestep::from_secs(1.0 / settings.record_rate);
+        let mut aspect = extent.width as f32 / extent.height as f32;
+        let mut previous_scene = Scene::default();
+        let mut scene = Scene::default();
+        let mut input = Input::new(HelloTriangleApplication::load_bindings(settings));
+        let mut camera_controller = HelloTriangleApplication::create_camera_controller();
+        let mut input_replay = HelloTriangleApplication::load_input_replay(settings);
+
+        let mut recorded_frames = 0;
+        while recorded_frames < frame_count {
+            let mut elapsed = frame_time;
+            input.begin_frame();
+            if let Some(ref mut input_replay) = input_replay {
+                match input_replay.next_frame() {
+                    Some(frame) => {
+                        elapsed = frame.elapsed;
+                        for event in frame.events.iter() {
+                            input.apply(event);
+                        }
+                        // the image keeps its size, only the first window's aspect ratio is kept
+                        if let Some(&(width, height)) = frame.extents.first() {
+                            aspect = width as f32 / height as f32;
+                        }
+                    }
+                    None => {
+                        info!("input replay finished");
+                        break;
+                    }
+                }
+            }
+            camera_controller.handle_input(&input);
+
+            timestep.accumulate(elapsed);
+            while timestep.step() {
+                previous_scene = scene;
+                scene.update(timestep.delta());
+                camera_controller.update(&mut scene.camera, timestep.delta());
+            }
+
+            // only one frame is in flight, its copy has to be read before the next one is made
+            device.wait_for_fence(&fences[0], std::u64::MAX).unwrap();
+            screenshots.finish_frame(&mut memory_allocator, 0);
+            device.reset_fence(&fences[0]).unwrap();
+            command_pools[0].reset();
+
+            upload_ring.begin_frame(0);
+            let frame_scene = previous_scene.interpolate(&scene, timestep.alpha());
+            let frame_uniforms_offset = upload_ring
+                .upload(&[frame_scene.frame_uniforms(aspect)])
+                .expect("upload ring is full!");
+            upload_ring.end_frame(0);
+
+            screenshots
+                .capture(
+                    &mut memory_allocator,
+                    0,
+                    format,
+                    image::Usage::TRANSFER_SRC,
+                    extent,
+                )
+                .expect("failed to capture frame!");
+            HelloTriangleApplication::record_command_buffer(
+                &mut command_buffers[0],
+                &FrameContext {
+                    frame: 0,
+                    pipeline: &pipeline,
+                    frame_descriptor_set: &frame_descriptor_set,
+                    scene: &frame_scene,
+                },
+                DrawTarget {
+                    render_graph: &mut render_graph,
+                    backbuffer,
+                    main_pass,
+                    image_index: 0,
+                    image: &*frame_images[0].0,
+                    extent,
+                    screenshots: &screenshots,
+                },
+                frame_uniforms_offset as u32,
+                None,
+                None,
+            );
+
+            let submission = queue::Submission {
+                command_buffers: std::slice::from_ref(&command_buffers[0]),
+                wait_semaphores:
+                    Vec::<(&<back::Backend as Backend>::Semaphore, pso::PipelineStage)>::new(),
+                signal_semaphores: Vec::<&<back::Backend as Backend>::Semaphore>::new(),
+            };
+            command_queues[0].submit(submission, Some(&*fences[0]));
+            recorded_frames += 1;
+        }
+
+        device.wait_idle().expect("Queues are not going idle!");
+        screenshots.finish(&mut memory_allocator);
+        info!("recorded {} frames", recorded_frames);
+
+        render_graph.release(&mut memory_allocator);
+        // the image goes before the memory it is bound to
+        drop(frame_images);
+        memory_allocator.free(image_allocation);
+        upload_ring.release(&mut memory_allocator);
     }
 
     fn main_loop(&mut self) {
         let mut current_frame: usize = 0;
+        let mut timestep = FixedTimestep::from_rate(self.settings.update_rate);
+        let min_frame_time = self
+            .settings
+            .frame_rate_cap
+            .map(|frame_rate| timestep::from_secs(1.0 / frame_rate));
+        let mut last_frame = Instant::now();
 
         let mut events_loop = self
-            .window_state
             .events_loop
             .take()
             .expect("events_loop does not exist!");
-        events_loop.run_forever(|event| match event {
-            Event::WindowEvent {
-                event: WindowEvent::CloseRequested,
-                ..
-            } => {
-                self.hal_state
-                    .device
-                    .wait_idle()
-                    .expect("Queues are not going idle!");
-                ControlFlow::Break
-            }
-            _ => {
+
+        while !self.windows.is_empty() {
+            let frame_span = profile::span("frame");
+            let frame_start = Instant::now();
+
+            let poll_span = profile::span("poll_events");
+            let mut events = Vec::new();
+            let mut closed_windows = Vec::new();
+            {
+                let windows = &mut self.windows;
+                let focused_window = &mut self.focused_window;
+                let cursor_window = &mut self.cursor_window;
+                events_loop.poll_events(|event| {
+                    if let Event::WindowEvent { window_id, event } = event {
+                        let target = match windows
+                            .iter_mut()
+                            .find(|target| target.window.id() == window_id)
+                        {
+                            Some(target) => target,
+                            None => return,
+                        };
+                        match event {
+                            WindowEvent::CloseRequested => closed_windows.push(window_id),
+                            // the physical size changes with the dpi even if the logical one
+                            // doesn't
+                            WindowEvent::Resized(_) | WindowEvent::HiDpiFactorChanged(_) => {
+                                target.recreate_swapchain = true
+                            }
+                            _ => {
+                                if let WindowEvent::Focused(true) = event {
+                                    *focused_window = Some(window_id);
+                                }
+                                let event = InputEvent::from_window_event(&event);
+                                // positions are relative to their window, so moving into another
+                                // one starts over instead of jumping by the distance between them
+                                if let Some(InputEvent::CursorMoved { .. }) = event {
+                                    if *cursor_window != Some(window_id) {
+                                        events.push(InputEvent::CursorLeft);
+                                        *cursor_window = Some(window_id);
+                                    }
+                                }
+                                events.extend(event)
+                            }
+                        }
+                    }
+                });
+            }
+            drop(poll_span);
+
+            for window_id in closed_windows {
+                if let Some(index) = self
+                    .windows
+                    .iter()
+                    .position(|target| target.window.id() == window_id)
+                {
+                    let target = self.windows.remove(index);
+                    unsafe {
+                        HelloTriangleApplication::close_window(&mut self.hal_state, target);
+                    }
+                }
+            }
+            let mut running = !self.windows.is_empty();
+
+            let mut elapsed = frame_start - last_frame;
+            last_frame = frame_start;
+            // a replayed frame ignores the real input and clock
+            if let Some(ref mut input_replay) = self.input_replay {
+                match input_replay.next_frame() {
+                    Some(frame) => {
+                        elapsed = frame.elapsed;
+                        events = frame.events;
+                        for (target, &(width, height)) in
+                            self.windows.iter_mut().zip(frame.extents.iter())
+                        {
+                            let extent = window::Extent2D { width, height };
+                            if target.replay_extent != Some(extent) && extent != target.extent {
+                                warn!(
+                                    "replaying a {}x{} window at {}x{}, only the aspect ratio \
+                                     matches the recording",
+                                    width, height, target.extent.width, target.extent.height
+                                );
+                            }
+                            target.replay_extent = Some(extent);
+                        }
+                    }
+                    None => {
+                        info!("input replay finished");
+                        running = false;
+                    }
+                }
+            }
+
+            if !running {
+                break;
+            }
+
+            self.input.begin_frame();
+            for event in events.iter() {
+                self.input.apply(event);
+            }
+            self.camera_controller.handle_input(&self.input);
+
+            // before any window was focused, e.g. when replaying, the first one is
+            let focused_window = self
+                .focused_window
+                .filter(|&window_id| {
+                    self.windows
+                        .iter()
+                        .any(|target| target.window.id() == window_id)
+                })
+                .unwrap_or_else(|| self.windows[0].window.id());
+            if let Some(target) = self
+                .windows
+                .iter_mut()
+                .find(|target| target.window.id() == focused_window)
+            {
+                if self.input.action_pressed(window_mode::TOGGLE_FULLSCREEN) {
+                    target.modes.toggle(&target.window, WindowMode::Fullscreen);
+                }
+                if self.input.action_pressed(window_mode::TOGGLE_BORDERLESS) {
+                    target.modes.toggle(&target.window, WindowMode::Borderless);
+                }
+            }
+            if self.input.action_pressed(OPEN_WINDOW) {
+                let opened = unsafe {
+                    HelloTriangleApplication::open_window(
+                        &mut self.hal_state,
+                        &events_loop,
+                        self.settings.window_mode,
+                    )
+                };
+                self.windows.extend(opened);
+            }
+
+            timestep.accumulate(elapsed);
+            while timestep.step() {
+                let _span = profile::span("update");
+                self.previous_scene = self.scene;
+                self.scene.update(timestep.delta());
+                self.camera_controller
+                    .update(&mut self.scene.camera, timestep.delta());
+            }
+
+            let mut timing = FrameTiming::default();
+            let screenshot_window = if self.input.action_pressed(screenshot::SCREENSHOT) {
+                Some(focused_window)
+            } else {
+                None
+            };
+            let drawn = unsafe {
+                HelloTriangleApplication::draw_frame(
+                    &mut self.hal_state,
+                    &mut self.windows,
+                    current_frame,
+                    &self
+                        .previous_scene
+                        .interpolate(&self.scene, timestep.alpha()),
+                    screenshot_window,
+                    &mut timing,
+                )
+            };
+
+            // after drawing, so the extents are the ones the frame was drawn at
+            if let Some(mut input_recorder) = self.input_recorder.take() {
+                let extents: Vec<replay::Extent> = self
+                    .windows
+                    .iter()
+                    .map(|target| (target.extent.width, target.extent.height))
+                    .collect();
+                match input_recorder.record_frame(elapsed, &extents, &events) {
+                    Ok(()) => self.input_recorder = Some(input_recorder),
+                    Err(err) => warn!("stopped recording input: {}", err),
+                }
+            }
+
+            while let Some(index) = self
+                .windows
+                .iter()
+                .position(|target| target.swapchain.is_none())
+            {
+                let target = self.windows.remove(index);
                 unsafe {
-                    HelloTriangleApplication::draw_frame(
-                        &self.hal_state.device,
-                        &mut self.hal_state.command_queues,
-                        &mut self.hal_state.swapchain,
-                        &self.hal_state.submission_command_buffers,
-                        &self.hal_state.image_available_semaphores[current_frame],
-                        &self.hal_state.render_finished_semaphores[current_frame],
-                        &self.hal_state.in_flight_fences[current_frame],
+                    HelloTriangleApplication::close_window(&mut self.hal_state, target);
+                }
+            }
+
+            if drawn {
+                current_frame = (current_frame + 1) % self.settings.frames_in_flight;
+
+                timing.cpu_frame = frame_start.elapsed();
+                self.frame_timer.record(timing);
+                if let Some(fps) = self.frame_timer.fps_report() {
+                    let stats = self.frame_timer.cpu_frame_stats();
+                    let gpu = self
+                        .frame_timer
+                        .gpu_render_pass_stats()
+                        .map(|gpu| format!(", gpu {:.2} ms", gpu.avg))
+                        .unwrap_or_default();
+                    let title = format!(
+                        "{} - {:.1} fps ({:.2}/{:.2}/{:.2}/{:.2} ms min/avg/max/p99{})",
+                        WINDOW_NAME, fps, stats.min, stats.avg, stats.max, stats.p99, gpu
                     );
+                    for target in self.windows.iter() {
+                        target.window.set_title(&title);
+                    }
+                    self.report_draw_statistics();
+                }
+            }
+            drop(frame_span);
+
+            // nothing waited for the gpu or the display, so without this the loop would spin
+            if !drawn {
+                thread::sleep(Duration::from_millis(IDLE_SLEEP_MS));
+            }
+
+            if let Some(min_frame_time) = min_frame_time {
+                let frame_time = frame_start.elapsed();
+                if frame_time < min_frame_time {
+                    thread::sleep(min_frame_time - frame_time);
                 }
+            }
+        }
 
-                current_frame = (current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
-                ;
-                ControlFlow::Continue
+        self.hal_state
+            .device
+            .wait_idle()
+            .expect("Queues are not going idle!");
+        self.events_loop = Some(events_loop);
+
+        if let Some(input_recorder) = self.input_recorder.take() {
+            if let Err(err) = input_recorder.finish() {
+                warn!("could not finish the input recording: {}", err);
             }
-        });
-        self.window_state.events_loop = Some(events_loop);
+        }
+    }
+
+    fn report_draw_statistics(&self) {
+        for (object, statistics) in self.hal_state.draw_statistics.iter().enumerate() {
+            let invocations = match (
+                statistics.vertex_shader_invocations,
+                statistics.fragment_shader_invocations,
+            ) {
+                (Some(vertex), Some(fragment)) => format!(
+                    ", {} vertex / {} fragment shader invocations",
+                    vertex, fragment
+                ),
+                _ => String::new(),
+            };
+            info!(
+                "object {}: {} samples passed{}",
+                object, statistics.samples_passed, invocations
+            );
+        }
+    }
+
+    fn report_frame_timings(&self) {
+        let cpu_frame = self.frame_timer.cpu_frame_stats();
+        let acquire_wait = self.frame_timer.acquire_wait_stats();
+        let fence_wait = self.frame_timer.fence_wait_stats();
+        if let Some(gpu_render_pass) = self.frame_timer.gpu_render_pass_stats() {
+            info!(
+                "gpu render pass {:.2}/{:.2}/{:.2}/{:.2} ms (min/avg/max/p99)",
+                gpu_render_pass.min, gpu_render_pass.avg, gpu_render_pass.max, gpu_render_pass.p99
+            );
+        }
+        info!(
+            "cpu frame {:.2}/{:.2}/{:.2}/{:.2} ms, acquire wait {:.2}/{:.2}/{:.2}/{:.2} ms, \
+             fence wait {:.2}/{:.2}/{:.2}/{:.2} ms (min/avg/max/p99)",
+            cpu_frame.min,
+            cpu_frame.avg,
+            cpu_frame.max,
+            cpu_frame.p99,
+            acquire_wait.min,
+            acquire_wait.avg,
+            acquire_wait.max,
+            acquire_wait.p99,
+            fence_wait.min,
+            fence_wait.avg,
+            fence_wait.max,
+            fence_wait.p99
+        );
+
+        if let Some(ref path) = self.settings.timings_csv {
+            if let Err(err) = self.frame_timer.write_csv(path) {
+                warn!(
+                    "could not write frame timings to {}: {}",
+                    path.display(),
+                    err
+                );
+            }
+        }
     }
 
     fn run(&mut self) {
         self.main_loop();
+        self.report_frame_timings();
     }
 
-    unsafe fn clean_up(self) {
+    unsafe fn clean_up(mut self) {
+        for target in self.windows.drain(..) {
+            HelloTriangleApplication::close_window(&mut self.hal_state, target);
+        }
         self.hal_state.clean_up();
     }
 }
@@ -0,0 +1,37 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(push_constant) uniform PushConstants {
+    vec2 offset;
+    float scale;
+} object;
+
+layout(set = 0, binding = 0) uniform FrameData {
+    mat4 view_projection;
+    float pulse;
+} frame;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+layout(location = 0) out vec3 fragColor;
+
+// in world space, y up
+vec2 positions[3] = vec2[](
+    vec2(0.0, 0.5),
+    vec2(0.5, -0.5),
+    vec2(-0.5, -0.5)
+);
+
+vec3 colors[3] = vec3[](
+    vec3(1.0, 0.0, 0.0),
+    vec3(0.0, 1.0, 0.0),
+    vec3(0.0, 0.0, 1.0)
+);
+
+void main() {
+    vec2 position = positions[gl_VertexIndex] * object.scale * (1.0 + 0.1 * frame.pulse) + object.offset;
+    gl_Position = frame.view_projection * vec4(position, 0.0, 1.0);
+    fragColor = colors[gl_VertexIndex];
+}
@@ -0,0 +1,500 @@
+// tracks the layout, last write and following reads of every subresource of the images
+// registered with it, so code can state the access it needs next and get only the barriers that
+// are actually required: layout changes, and accesses that haven't waited for the last write or
+// writes that haven't waited for the reads before them; aspects of an image are always tracked
+// together
+use back;
+use common::query::FrameCommandBuffer;
+use hal::{buffer, image, memory, pso, Backend};
+use std::ops::Range;
+
+pub type ImageId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageState {
+    pub stages: pso::PipelineStage,
+    pub access: image::Access,
+    pub layout: image::Layout,
+}
+
+impl ImageState {
+    pub fn new(
+        stages: pso::PipelineStage,
+        access: image::Access,
+        layout: image::Layout,
+    ) -> ImageState {
+        ImageState {
+            stages,
+            access,
+            layout,
+        }
+    }
+
+    fn writes(&self) -> bool {
+        self.access.intersects(
+            image::Access::SHADER_WRITE
+                | image::Access::COLOR_ATTACHMENT_WRITE
+                | image::Access::DEPTH_STENCIL_ATTACHMENT_WRITE
+                | image::Access::TRANSFER_WRITE
+                | image::Access::HOST_WRITE
+                | image::Access::MEMORY_WRITE,
+        )
+    }
+}
+
+// what happened to a subresource since it was last written
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TrackedState {
+    layout: image::Layout,
+    // the last write, or the stages that waited for the last layout transition; every later
+    // access has to wait for these
+    write_stages: pso::PipelineStage,
+    write_access: image::Access,
+    // reads since then that already wait for it, the next write or transition waits for all of
+    // them
+    read_stages: pso::PipelineStage,
+    read_access: image::Access,
+}
+
+impl TrackedState {
+    // a state set from outside is taken as reached, so only accesses by other stages wait for it
+    fn new(state: ImageState) -> TrackedState {
+        if state.writes() {
+            TrackedState {
+                layout: state.layout,
+                write_stages: state.stages,
+                write_access: state.access,
+                read_stages: pso::PipelineStage::empty(),
+                read_access: image::Access::empty(),
+            }
+        } else {
+            TrackedState {
+                layout: state.layout,
+                write_stages: state.stages,
+                write_access: image::Access::empty(),
+                read_stages: state.stages,
+                read_access: state.access,
+            }
+        }
+    }
+
+    // the state a barrier before `next` has to start from, None if it needs no barrier
+    fn barrier_before(&self, next: &ImageState) -> Option<ImageState> {
+        if self.layout != next.layout || next.writes() {
+            return Some(ImageState::new(
+                self.write_stages | self.read_stages,
+                self.write_access,
+                self.layout,
+            ));
+        }
+
+        // a read in the same layout only waits for the write, and only if it hasn't already
+        let waited =
+            self.read_stages.contains(next.stages) && self.read_access.contains(next.access);
+        if waited || self.write_stages.is_empty() {
+            None
+        } else {
+            Some(ImageState::new(
+                self.write_stages,
+                self.write_access,
+                self.layout,
+            ))
+        }
+    }
+
+    // after `next`, with or without a barrier before it
+    fn access(&mut self, next: &ImageState) {
+        if next.writes() {
+            *self = TrackedState::new(*next);
+        } else if self.layout != next.layout {
+            // the transition is a write that only the stages of this read waited for
+            *self = TrackedState {
+                layout: next.layout,
+                write_stages: next.stages,
+                write_access: image::Access::empty(),
+                read_stages: next.stages,
+                read_access: next.access,
+            };
+        } else {
+            self.read_stages |= next.stages;
+            self.read_access |= next.access;
+        }
+    }
+}
+
+// barriers collected from the tracker, recorded together as a single pipeline barrier
+pub struct Barriers<'a> {
+    stages: Range<pso::PipelineStage>,
+    barriers: Vec<memory::Barrier<'a, back::Backend>>,
+}
+
+impl<'a> Barriers<'a> {
+    pub fn new() -> Barriers<'a> {
+        Barriers {
+            stages: pso::PipelineStage::empty()..pso::PipelineStage::empty(),
+            barriers: Vec::new(),
+        }
+    }
+
+    fn push_image(
+        &mut self,
+        image: &'a <back::Backend as Backend>::Image,
+        range: image::SubresourceRange,
+        from: ImageState,
+        to: ImageState,
+    ) {
+        self.stages.start |= from.stages;
+        self.stages.end |= to.stages;
+        self.barriers.push(memory::Barrier::Image {
+            states: (from.access, from.layout)..(to.access, to.layout),
+            target: image,
+            range,
+        });
+    }
+
+    // buffers aren't tracked, the caller knows how they were used last
+    pub fn buffer(
+        &mut self,
+        buffer: &'a <back::Backend as Backend>::Buffer,
+        from: (pso::PipelineStage, buffer::Access),
+        to: (pso::PipelineStage, buffer::Access),
+    ) {
+        self.stages.start |= from.0;
+        self.stages.end |= to.0;
+        self.barriers.push(memory::Barrier::Buffer {
+            states: from.1..to.1,
+            target: buffer,
+        });
+    }
+
+    pub unsafe fn record(self, command_buffer: &mut FrameCommandBuffer) {
+        if self.barriers.is_empty() {
+            return;
+        }
+
+        // nothing to wait for (e.g. undefined images) still needs a valid stage mask
+        let src_stages = if self.stages.start.is_empty() {
+            pso::PipelineStage::TOP_OF_PIPE
+        } else {
+            self.stages.start
+        };
+        let dst_stages = if self.stages.end.is_empty() {
+            pso::PipelineStage::BOTTOM_OF_PIPE
+        } else {
+            self.stages.end
+        };
+
+        command_buffer.pipeline_barrier(
+            src_stages..dst_stages,
+            memory::Dependencies::empty(),
+            self.barriers,
+        );
+    }
+}
+
+impl<'a> Default for Barriers<'a> {
+    fn default() -> Barriers<'a> {
+        Barriers::new()
+    }
+}
+
+struct TrackedImage {
+    layers: u16,
+    // indexed by level * layers + layer
+    states: Vec<TrackedState>,
+}
+
+impl TrackedImage {
+    fn state_mut(&mut self, level: u8, layer: u16) -> &mut TrackedState {
+        &mut self.states[level as usize * self.layers as usize + layer as usize]
+    }
+}
+
+#[derive(Default)]
+pub struct ImageTracker {
+    images: Vec<Option<TrackedImage>>,
+}
+
+impl ImageTracker {
+    pub fn new() -> ImageTracker {
+        ImageTracker::default()
+    }
+
+    pub fn register(&mut self, levels: u8, layers: u16, initial: ImageState) -> ImageId {
+        let image = TrackedImage {
+            layers,
+            states: vec![TrackedState::new(initial); levels as usize * layers as usize],
+        };
+
+        match self.images.iter().position(|image| image.is_none()) {
+            Some(id) => {
+                self.images[id] = Some(image);
+                id
+            }
+            None => {
+                self.images.push(Some(image));
+                self.images.len() - 1
+            }
+        }
+    }
+
+    pub fn unregister(&mut self, id: ImageId) {
+        self.images[id] = None;
+    }
+
+    // the next access doesn't care about the contents, so the transition can start from undefined
+    pub fn discard(&mut self, id: ImageId, range: &image::SubresourceRange) {
+        self.for_each_state(id, range, |state| state.layout = image::Layout::Undefined);
+    }
+
+    // for state changes that happen outside the tracker, e.g. a render pass' final layout
+    pub fn assume(&mut self, id: ImageId, range: &image::SubresourceRange, next: ImageState) {
+        self.for_each_state(id, range, |state| *state = TrackedState::new(next));
+    }
+
+    // adds the barriers needed before `range` of the image can be used as described by `next`
+    pub fn require<'a>(
+        &mut self,
+        barriers: &mut Barriers<'a>,
+        id: ImageId,
+        image: &'a <back::Backend as Backend>::Image,
+        range: &image::SubresourceRange,
+        next: ImageState,
+    ) {
+        for (range, from) in self.transitions(id, range, next) {
+            barriers.push_image(image, range, from, next);
+        }
+    }
+
+    // the ranges that need a barrier before `next` and the state each one starts from
+    fn transitions(
+        &mut self,
+        id: ImageId,
+        range: &image::SubresourceRange,
+        next: ImageState,
+    ) -> Vec<(image::SubresourceRange, ImageState)> {
+        let tracked = self.images[id].as_mut().expect("image is not tracked!");
+        // runs of layers in the same state, merged across levels where they line up
+        let mut runs: Vec<(Range<u8>, Range<u16>, ImageState)> = Vec::new();
+
+        for level in range.levels.clone() {
+            let mut layer = range.layers.start;
+            while layer < range.layers.end {
+                let start = layer;
+                let state = *tracked.state_mut(level, layer);
+                while layer < range.layers.end && *tracked.state_mut(level, layer) == state {
+                    layer += 1;
+                }
+
+                let from = state.barrier_before(&next);
+                for run_layer in start..layer {
+                    tracked.state_mut(level, run_layer).access(&next);
+                }
+                let from = match from {
+                    Some(from) => from,
+                    None => continue,
+                };
+
+                let merged = match runs.last_mut() {
+                    Some(run) if run.0.end == level && run.1 == (start..layer) && run.2 == from => {
+                        run.0.end += 1;
+                        true
+                    }
+                    _ => false,
+                };
+                if !merged {
+                    runs.push((level..level + 1, start..layer, from));
+                }
+            }
+        }
+
+        runs.into_iter()
+            .map(|(levels, layers, from)| {
+                (
+                    image::SubresourceRange {
+                        aspects: range.aspects,
+                        levels,
+                        layers,
+                    },
+                    from,
+                )
+            })
+            .collect()
+    }
+
+    fn for_each_state<F: FnMut(&mut TrackedState)>(
+        &mut self,
+        id: ImageId,
+        range: &image::SubresourceRange,
+        mut f: F,
+    ) {
+        let tracked = self.images[id].as_mut().expect("image is not tracked!");
+        for level in range.levels.clone() {
+            for layer in range.layers.clone() {
+                f(tracked.state_mut(level, layer));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hal::format;
+
+    fn range(levels: Range<u8>, layers: Range<u16>) -> image::SubresourceRange {
+        image::SubresourceRange {
+            aspects: format::Aspects::COLOR,
+            levels,
+            layers,
+        }
+    }
+
+    fn sampled(stages: pso::PipelineStage) -> ImageState {
+        ImageState::new(
+            stages,
+            image::Access::SHADER_READ,
+            image::Layout::ShaderReadOnlyOptimal,
+        )
+    }
+
+    fn color_write() -> ImageState {
+        ImageState::new(
+            pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+            image::Access::COLOR_ATTACHMENT_READ | image::Access::COLOR_ATTACHMENT_WRITE,
+            image::Layout::ColorAttachmentOptimal,
+        )
+    }
+
+    fn untouched(layout: image::Layout) -> ImageState {
+        ImageState::new(pso::PipelineStage::empty(), image::Access::empty(), layout)
+    }
+
+    #[test]
+    fn reads_in_the_same_layout_are_merged() {
+        let mut tracker = ImageTracker::new();
+        let id = tracker.register(1, 1, untouched(image::Layout::ShaderReadOnlyOptimal));
+        let whole = range(0..1, 0..1);
+
+        let fragment = sampled(pso::PipelineStage::FRAGMENT_SHADER);
+        let vertex = sampled(pso::PipelineStage::VERTEX_SHADER);
+        assert!(tracker.transitions(id, &whole, fragment).is_empty());
+        assert!(tracker.transitions(id, &whole, vertex).is_empty());
+
+        // the write waits for every read before it
+        assert_eq!(
+            tracker.transitions(id, &whole, color_write()),
+            vec![(
+                whole.clone(),
+                ImageState::new(
+                    pso::PipelineStage::FRAGMENT_SHADER | pso::PipelineStage::VERTEX_SHADER,
+                    image::Access::empty(),
+                    image::Layout::ShaderReadOnlyOptimal,
+                ),
+            )]
+        );
+    }
+
+    #[test]
+    fn reads_wait_for_the_last_transition_once() {
+        let mut tracker = ImageTracker::new();
+        let id = tracker.register(1, 1, untouched(image::Layout::Undefined));
+        let whole = range(0..1, 0..1);
+
+        assert_eq!(
+            tracker.transitions(id, &whole, color_write()),
+            vec![(whole.clone(), untouched(image::Layout::Undefined))]
+        );
+        let fragment = sampled(pso::PipelineStage::FRAGMENT_SHADER);
+        assert_eq!(
+            tracker.transitions(id, &whole, fragment),
+            vec![(whole.clone(), color_write())]
+        );
+        assert!(tracker.transitions(id, &whole, fragment).is_empty());
+
+        // the transition happened before the fragment shader, so the vertex shader waits for that
+        let vertex = sampled(pso::PipelineStage::VERTEX_SHADER);
+        assert_eq!(
+            tracker.transitions(id, &whole, vertex),
+            vec![(
+                whole.clone(),
+                ImageState::new(
+                    pso::PipelineStage::FRAGMENT_SHADER,
+                    image::Access::empty(),
+                    image::Layout::ShaderReadOnlyOptimal,
+                ),
+            )]
+        );
+        assert!(tracker.transitions(id, &whole, fragment).is_empty());
+        assert!(tracker.transitions(id, &whole, vertex).is_empty());
+    }
+
+    #[test]
+    fn writes_and_reads_in_the_same_layout_wait_for_each_other() {
+        let mut tracker = ImageTracker::new();
+        let id = tracker.register(1, 1, untouched(image::Layout::General));
+        let whole = range(0..1, 0..1);
+        let read =
+            |stages| ImageState::new(stages, image::Access::SHADER_READ, image::Layout::General);
+        let write = ImageState::new(
+            pso::PipelineStage::COMPUTE_SHADER,
+            image::Access::SHADER_WRITE,
+            image::Layout::General,
+        );
+
+        assert!(tracker
+            .transitions(id, &whole, read(pso::PipelineStage::FRAGMENT_SHADER))
+            .is_empty());
+        assert_eq!(
+            tracker.transitions(id, &whole, write),
+            vec![(
+                whole.clone(),
+                ImageState::new(
+                    pso::PipelineStage::FRAGMENT_SHADER,
+                    image::Access::empty(),
+                    image::Layout::General,
+                ),
+            )]
+        );
+
+        // every reader waits for the write itself, not for the readers before it
+        for &stages in [
+            pso::PipelineStage::FRAGMENT_SHADER,
+            pso::PipelineStage::VERTEX_SHADER,
+        ]
+        .iter()
+        {
+            assert_eq!(
+                tracker.transitions(id, &whole, read(stages)),
+                vec![(whole.clone(), write)]
+            );
+        }
+    }
+
+    #[test]
+    fn transitions_only_cover_subresources_that_change() {
+        let mut tracker = ImageTracker::new();
+        let id = tracker.register(2, 2, untouched(image::Layout::Undefined));
+        let upload = ImageState::new(
+            pso::PipelineStage::TRANSFER,
+            image::Access::TRANSFER_WRITE,
+            image::Layout::TransferDstOptimal,
+        );
+
+        assert_eq!(
+            tracker.transitions(id, &range(0..1, 0..2), upload),
+            vec![(range(0..1, 0..2), untouched(image::Layout::Undefined))]
+        );
+        assert_eq!(
+            tracker.transitions(
+                id,
+                &range(0..2, 0..2),
+                sampled(pso::PipelineStage::FRAGMENT_SHADER)
+            ),
+            vec![
+                (range(0..1, 0..2), upload),
+                (range(1..2, 0..2), untouched(image::Layout::Undefined)),
+            ]
+        );
+    }
+}
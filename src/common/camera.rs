@@ -0,0 +1,353 @@
+// right handed camera looking down -z in view space, with projections into gfx-hal's clip space,
+// which like vulkan's has y pointing down and depth going from 0 to 1
+//
+// matrices are column major, `m[column][row]`, so they can be copied into glsl's `mat4` as is
+use common::input::Input;
+use std::f32::consts::PI;
+
+pub type Vec3 = [f32; 3];
+pub type Mat4 = [[f32; 4]; 4];
+
+// looking straight up or down would make the view direction parallel to the up vector
+const MAX_PITCH: f32 = 89.0 * PI / 180.0;
+const MIN_ORBIT_DISTANCE: f32 = 0.1;
+
+fn add(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn scale(a: Vec3, factor: f32) -> Vec3 {
+    [a[0] * factor, a[1] * factor, a[2] * factor]
+}
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: Vec3) -> Vec3 {
+    scale(a, 1.0 / dot(a, a).sqrt())
+}
+
+fn lerp(a: f32, b: f32, alpha: f32) -> f32 {
+    a + (b - a) * alpha
+}
+
+pub fn multiply(a: &Mat4, b: &Mat4) -> Mat4 {
+    let mut result = [[0.0; 4]; 4];
+    for column in 0..4 {
+        for row in 0..4 {
+            result[column][row] = (0..4).map(|k| a[k][row] * b[column][k]).sum();
+        }
+    }
+    result
+}
+
+pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Mat4 {
+    let forward = normalize(add(target, scale(eye, -1.0)));
+    let side = normalize(cross(forward, up));
+    let up = cross(side, forward);
+
+    [
+        [side[0], up[0], -forward[0], 0.0],
+        [side[1], up[1], -forward[1], 0.0],
+        [side[2], up[2], -forward[2], 0.0],
+        [-dot(side, eye), -dot(up, eye), dot(forward, eye), 1.0],
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    // vertical field of view in radians
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    // height of the view volume in world units, the width follows from the aspect ratio
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+impl Projection {
+    // the negated y scale is the flip from y up in view space to y down in clip space
+    pub fn matrix(&self, aspect: f32) -> Mat4 {
+        match *self {
+            Projection::Perspective { fov_y, near, far } => {
+                let focal = 1.0 / (fov_y / 2.0).tan();
+                [
+                    [focal / aspect, 0.0, 0.0, 0.0],
+                    [0.0, -focal, 0.0, 0.0],
+                    [0.0, 0.0, far / (near - far), -1.0],
+                    [0.0, 0.0, near * far / (near - far), 0.0],
+                ]
+            }
+            Projection::Orthographic { height, near, far } => [
+                [2.0 / (height * aspect), 0.0, 0.0, 0.0],
+                [0.0, -2.0 / height, 0.0, 0.0],
+                [0.0, 0.0, 1.0 / (near - far), 0.0],
+                [0.0, 0.0, near / (near - far), 1.0],
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    // radians; a yaw of 0 looks down -z, positive pitch looks up
+    pub yaw: f32,
+    pub pitch: f32,
+    pub projection: Projection,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera {
+            position: [0.0, 0.0, 2.0],
+            yaw: 0.0,
+            pitch: 0.0,
+            projection: Projection::Perspective {
+                fov_y: PI / 4.0,
+                near: 0.1,
+                far: 100.0,
+            },
+        }
+    }
+}
+
+impl Camera {
+    pub fn forward(&self) -> Vec3 {
+        [
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            -self.pitch.cos() * self.yaw.cos(),
+        ]
+    }
+
+    pub fn right(&self) -> Vec3 {
+        [self.yaw.cos(), 0.0, self.yaw.sin()]
+    }
+
+    pub fn view(&self) -> Mat4 {
+        look_at(
+            self.position,
+            add(self.position, self.forward()),
+            [0.0, 1.0, 0.0],
+        )
+    }
+
+    pub fn view_projection(&self, aspect: f32) -> Mat4 {
+        multiply(&self.projection.matrix(aspect), &self.view())
+    }
+
+    // for rendering between two simulation steps; the projection isn't blended
+    pub fn interpolate(&self, next: &Camera, alpha: f32) -> Camera {
+        Camera {
+            position: [
+                lerp(self.position[0], next.position[0], alpha),
+                lerp(self.position[1], next.position[1], alpha),
+                lerp(self.position[2], next.position[2], alpha),
+            ],
+            yaw: lerp(self.yaw, next.yaw, alpha),
+            pitch: lerp(self.pitch, next.pitch, alpha),
+            projection: next.projection,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlMode {
+    // dragging with the look button circles the target, scrolling zooms
+    Orbit,
+    // the move actions move, dragging with the look button looks around
+    Fly,
+}
+
+pub const MOVE_FORWARD: &str = "move_forward";
+pub const MOVE_BACK: &str = "move_back";
+pub const MOVE_LEFT: &str = "move_left";
+pub const MOVE_RIGHT: &str = "move_right";
+pub const MOVE_UP: &str = "move_up";
+pub const MOVE_DOWN: &str = "move_down";
+pub const LOOK: &str = "look";
+pub const SWITCH_MODE: &str = "switch_camera_mode";
+pub const SWITCH_PROJECTION: &str = "switch_projection";
+
+// in the format read by `Bindings::parse`
+pub const DEFAULT_BINDINGS: &str = "\
+move_forward = W, Up
+move_back = S, Down
+move_left = A, Left
+move_right = D, Right
+move_up = E
+move_down = Q
+look = MouseLeft
+switch_camera_mode = Tab
+switch_projection = P
+";
+
+#[derive(Clone, Copy, Debug, Default)]
+struct HeldKeys {
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+}
+
+// turns the frame's input into camera movement; input is only collected by `handle_input` and
+// applied to the camera in fixed steps by `update`, so it's part of the simulation
+pub struct CameraController {
+    mode: ControlMode,
+    target: Vec3,
+    distance: f32,
+    held: HeldKeys,
+    // input collected since the last update
+    look: (f32, f32),
+    zoom: f32,
+    toggle_projection: bool,
+    // world units per second
+    pub move_speed: f32,
+    // radians per logical pixel the cursor moves
+    pub look_sensitivity: f32,
+}
+
+impl CameraController {
+    pub fn orbit(target: Vec3, distance: f32) -> CameraController {
+        CameraController {
+            mode: ControlMode::Orbit,
+            target,
+            distance,
+            held: HeldKeys::default(),
+            look: (0.0, 0.0),
+            zoom: 0.0,
+            toggle_projection: false,
+            move_speed: 2.0,
+            look_sensitivity: 0.005,
+        }
+    }
+
+    // call once per frame, after the frame's events were handled
+    pub fn handle_input(&mut self, input: &Input) {
+        self.held = HeldKeys {
+            forward: input.action_held(MOVE_FORWARD),
+            back: input.action_held(MOVE_BACK),
+            left: input.action_held(MOVE_LEFT),
+            right: input.action_held(MOVE_RIGHT),
+            up: input.action_held(MOVE_UP),
+            down: input.action_held(MOVE_DOWN),
+        };
+
+        if input.action_held(LOOK) {
+            let (x, y) = input.cursor_delta();
+            self.look.0 += x as f32 * self.look_sensitivity;
+            self.look.1 -= y as f32 * self.look_sensitivity;
+        }
+        self.zoom += input.scroll();
+
+        if input.action_pressed(SWITCH_MODE) {
+            self.mode = match self.mode {
+                ControlMode::Orbit => ControlMode::Fly,
+                ControlMode::Fly => ControlMode::Orbit,
+            };
+        }
+        self.toggle_projection |= input.action_pressed(SWITCH_PROJECTION);
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, delta: f32) {
+        camera.yaw += self.look.0;
+        camera.pitch = (camera.pitch + self.look.1).clamp(-MAX_PITCH, MAX_PITCH);
+
+        match self.mode {
+            ControlMode::Orbit => {
+                // each line scrolled moves a tenth of the way towards the target
+                self.distance = (self.distance * 0.9f32.powf(self.zoom)).max(MIN_ORBIT_DISTANCE);
+                camera.position = add(self.target, scale(camera.forward(), -self.distance));
+            }
+            ControlMode::Fly => {
+                let step = self.move_speed * delta;
+                let axis = |positive: bool, negative: bool| {
+                    (positive as i32 - negative as i32) as f32 * step
+                };
+                let forward = axis(self.held.forward, self.held.back) + self.zoom * 0.1;
+                let right = axis(self.held.right, self.held.left);
+                let up = axis(self.held.up, self.held.down);
+
+                camera.position = add(camera.position, scale(camera.forward(), forward));
+                camera.position = add(camera.position, scale(camera.right(), right));
+                camera.position = add(camera.position, [0.0, up, 0.0]);
+                // switching back to orbiting circles whatever is in front of the camera
+                self.target = add(camera.position, scale(camera.forward(), self.distance));
+            }
+        }
+
+        if self.toggle_projection {
+            // objects at the orbit distance keep their size on screen
+            camera.projection = match camera.projection {
+                Projection::Perspective { fov_y, near, far } => Projection::Orthographic {
+                    height: 2.0 * self.distance * (fov_y / 2.0).tan(),
+                    near,
+                    far,
+                },
+                Projection::Orthographic { height, near, far } => Projection::Perspective {
+                    fov_y: 2.0 * (height / (2.0 * self.distance)).atan(),
+                    near,
+                    far,
+                },
+            };
+        }
+
+        self.look = (0.0, 0.0);
+        self.zoom = 0.0;
+        self.toggle_projection = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // depth after the perspective divide of a point straight ahead at `distance`
+    fn depth(projection: &Projection, distance: f32) -> f32 {
+        let matrix = projection.matrix(1.5);
+        let z = -distance;
+        let clip_z = matrix[2][2] * z + matrix[3][2];
+        let clip_w = matrix[2][3] * z + matrix[3][3];
+        clip_z / clip_w
+    }
+
+    #[test]
+    fn projections_map_near_and_far_to_the_depth_range() {
+        let projections = [
+            Projection::Perspective {
+                fov_y: 1.0,
+                near: 0.1,
+                far: 100.0,
+            },
+            Projection::Orthographic {
+                height: 4.0,
+                near: 0.5,
+                far: 20.0,
+            },
+        ];
+
+        for projection in projections.iter() {
+            let (near, far) = match *projection {
+                Projection::Perspective { near, far, .. }
+                | Projection::Orthographic { near, far, .. } => (near, far),
+            };
+            assert!(depth(projection, near).abs() < 1e-5, "{:?}", projection);
+            assert!(
+                (depth(projection, far) - 1.0).abs() < 1e-5,
+                "{:?}",
+                projection
+            );
+            let middle = depth(projection, (near + far) / 2.0);
+            assert!(middle > 0.0 && middle < 1.0, "{:?}", projection);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use common::timestep;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// number of most recent frames the rolling statistics are computed over
+const ROLLING_WINDOW: usize = 1000;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTiming {
+    // everything the cpu spent on the frame, including the waits below
+    pub cpu_frame: Duration,
+    // blocked in `acquire_image` waiting for the next swapchain image
+    pub acquire_wait: Duration,
+    // blocked waiting for the fences of earlier frames to signal
+    pub fence_wait: Duration,
+    // time between the timestamps around the render pass of an earlier frame, whose results
+    // became available during this one
+    pub gpu_render_pass: Option<Duration>,
+}
+
+// in milliseconds
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+    pub p99: f64,
+}
+
+impl Stats {
+    fn from_durations<I: Iterator<Item = Duration>>(durations: I) -> Stats {
+        let mut samples: Vec<f64> = durations
+            .map(|duration| timestep::as_secs(duration) * 1000.0)
+            .collect();
+        if samples.is_empty() {
+            return Stats::default();
+        }
+
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let p99_index = ((samples.len() as f64 * 0.99).ceil() as usize).max(1) - 1;
+
+        Stats {
+            min: samples[0],
+            avg: samples.iter().sum::<f64>() / samples.len() as f64,
+            max: samples[samples.len() - 1],
+            p99: samples[p99_index],
+        }
+    }
+}
+
+pub struct FrameTimer {
+    recent: VecDeque<FrameTiming>,
+    // every frame since startup, only kept if it is going to be written out
+    history: Option<Vec<FrameTiming>>,
+    frames_since_report: u32,
+    last_report: Instant,
+}
+
+impl FrameTimer {
+    pub fn new(keep_history: bool) -> FrameTimer {
+        FrameTimer {
+            recent: VecDeque::with_capacity(ROLLING_WINDOW),
+            history: if keep_history { Some(Vec::new()) } else { None },
+            frames_since_report: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, timing: FrameTiming) {
+        if self.recent.len() == ROLLING_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(timing);
+
+        if let Some(ref mut history) = self.history {
+            history.push(timing);
+        }
+
+        self.frames_since_report += 1;
+    }
+
+    pub fn cpu_frame_stats(&self) -> Stats {
+        Stats::from_durations(self.recent.iter().map(|timing| timing.cpu_frame))
+    }
+
+    pub fn acquire_wait_stats(&self) -> Stats {
+        Stats::from_durations(self.recent.iter().map(|timing| timing.acquire_wait))
+    }
+
+    pub fn fence_wait_stats(&self) -> Stats {
+        Stats::from_durations(self.recent.iter().map(|timing| timing.fence_wait))
+    }
+
+    pub fn gpu_render_pass_stats(&self) -> Option<Stats> {
+        if self
+            .recent
+            .iter()
+            .all(|timing| timing.gpu_render_pass.is_none())
+        {
+            return None;
+        }
+
+        Some(Stats::from_durations(
+            self.recent
+                .iter()
+                .filter_map(|timing| timing.gpu_render_pass),
+        ))
+    }
+
+    // frames per second over the last second, at most once per second
+    pub fn fps_report(&mut self) -> Option<f64> {
+        let elapsed = self.last_report.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return None;
+        }
+
+        let fps = f64::from(self.frames_since_report) / timestep::as_secs(elapsed);
+        self.frames_since_report = 0;
+        self.last_report = Instant::now();
+        Some(fps)
+    }
+
+    pub fn write_csv(&self, path: &Path) -> io::Result<()> {
+        let history = match self.history {
+            Some(ref history) => history,
+            None => return Ok(()),
+        };
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "frame,cpu_frame_ms,acquire_wait_ms,fence_wait_ms,gpu_render_pass_ms"
+        )?;
+        for (frame, timing) in history.iter().enumerate() {
+            let gpu_render_pass = timing
+                .gpu_render_pass
+                .map(|duration| format!("{:.4}", timestep::as_secs(duration) * 1000.0))
+                .unwrap_or_default();
+            writeln!(
+                writer,
+                "{},{:.4},{:.4},{:.4},{}",
+                frame,
+                timestep::as_secs(timing.cpu_frame) * 1000.0,
+                timestep::as_secs(timing.acquire_wait) * 1000.0,
+                timestep::as_secs(timing.fence_wait) * 1000.0,
+                gpu_render_pass
+            )?;
+        }
+
+        writer.flush()
+    }
+}
@@ -0,0 +1,408 @@
+// keyboard, mouse button, cursor and scroll state collected from window events over a frame, with
+// named actions bound to buttons so application code doesn't hard code keys
+//
+// bindings are written one action per line as `action = button, button...`, where a button is a
+// key like `W`, `Space` or `F12`, or one of `MouseLeft`, `MouseRight` and `MouseMiddle`; lines
+// starting with `#` are comments
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use winit::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+// names used in bindings and recordings; keys without one can't be bound
+const KEY_NAMES: [(&str, VirtualKeyCode); 64] = [
+    ("A", VirtualKeyCode::A),
+    ("B", VirtualKeyCode::B),
+    ("C", VirtualKeyCode::C),
+    ("D", VirtualKeyCode::D),
+    ("E", VirtualKeyCode::E),
+    ("F", VirtualKeyCode::F),
+    ("G", VirtualKeyCode::G),
+    ("H", VirtualKeyCode::H),
+    ("I", VirtualKeyCode::I),
+    ("J", VirtualKeyCode::J),
+    ("K", VirtualKeyCode::K),
+    ("L", VirtualKeyCode::L),
+    ("M", VirtualKeyCode::M),
+    ("N", VirtualKeyCode::N),
+    ("O", VirtualKeyCode::O),
+    ("P", VirtualKeyCode::P),
+    ("Q", VirtualKeyCode::Q),
+    ("R", VirtualKeyCode::R),
+    ("S", VirtualKeyCode::S),
+    ("T", VirtualKeyCode::T),
+    ("U", VirtualKeyCode::U),
+    ("V", VirtualKeyCode::V),
+    ("W", VirtualKeyCode::W),
+    ("X", VirtualKeyCode::X),
+    ("Y", VirtualKeyCode::Y),
+    ("Z", VirtualKeyCode::Z),
+    ("0", VirtualKeyCode::Key0),
+    ("1", VirtualKeyCode::Key1),
+    ("2", VirtualKeyCode::Key2),
+    ("3", VirtualKeyCode::Key3),
+    ("4", VirtualKeyCode::Key4),
+    ("5", VirtualKeyCode::Key5),
+    ("6", VirtualKeyCode::Key6),
+    ("7", VirtualKeyCode::Key7),
+    ("8", VirtualKeyCode::Key8),
+    ("9", VirtualKeyCode::Key9),
+    ("F1", VirtualKeyCode::F1),
+    ("F2", VirtualKeyCode::F2),
+    ("F3", VirtualKeyCode::F3),
+    ("F4", VirtualKeyCode::F4),
+    ("F5", VirtualKeyCode::F5),
+    ("F6", VirtualKeyCode::F6),
+    ("F7", VirtualKeyCode::F7),
+    ("F8", VirtualKeyCode::F8),
+    ("F9", VirtualKeyCode::F9),
+    ("F10", VirtualKeyCode::F10),
+    ("F11", VirtualKeyCode::F11),
+    ("F12", VirtualKeyCode::F12),
+    ("Escape", VirtualKeyCode::Escape),
+    ("Tab", VirtualKeyCode::Tab),
+    ("Space", VirtualKeyCode::Space),
+    ("Return", VirtualKeyCode::Return),
+    ("Back", VirtualKeyCode::Back),
+    ("Delete", VirtualKeyCode::Delete),
+    ("Left", VirtualKeyCode::Left),
+    ("Right", VirtualKeyCode::Right),
+    ("Up", VirtualKeyCode::Up),
+    ("Down", VirtualKeyCode::Down),
+    ("LShift", VirtualKeyCode::LShift),
+    ("RShift", VirtualKeyCode::RShift),
+    ("LControl", VirtualKeyCode::LControl),
+    ("RControl", VirtualKeyCode::RControl),
+    ("LAlt", VirtualKeyCode::LAlt),
+    ("RAlt", VirtualKeyCode::RAlt),
+];
+
+const MOUSE_NAMES: [(&str, MouseButton); 3] = [
+    ("MouseLeft", MouseButton::Left),
+    ("MouseRight", MouseButton::Right),
+    ("MouseMiddle", MouseButton::Middle),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+impl Button {
+    pub fn from_name(name: &str) -> Option<Button> {
+        KEY_NAMES
+            .iter()
+            .find(|&&(key_name, _)| key_name == name)
+            .map(|&(_, key)| Button::Key(key))
+            .or_else(|| {
+                MOUSE_NAMES
+                    .iter()
+                    .find(|&&(button_name, _)| button_name == name)
+                    .map(|&(_, button)| Button::Mouse(button))
+            })
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            Button::Key(key) => KEY_NAMES
+                .iter()
+                .find(|&&(_, named)| named == key)
+                .map(|&(name, _)| name),
+            Button::Mouse(button) => MOUSE_NAMES
+                .iter()
+                .find(|&&(_, named)| named == button)
+                .map(|&(name, _)| name),
+        }
+    }
+}
+
+// the parts of window events input cares about, so they can be recorded and replayed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    Button { button: Button, pressed: bool },
+    CursorMoved { x: f64, y: f64 },
+    CursorLeft,
+    // in lines
+    Scroll(f32),
+    Unfocused,
+}
+
+impl InputEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<InputEvent> {
+        match *event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => Some(InputEvent::Button {
+                button: Button::Key(key),
+                pressed: state == ElementState::Pressed,
+            }),
+            WindowEvent::MouseInput { state, button, .. } => Some(InputEvent::Button {
+                button: Button::Mouse(button),
+                pressed: state == ElementState::Pressed,
+            }),
+            WindowEvent::CursorMoved { position, .. } => Some(InputEvent::CursorMoved {
+                x: position.x,
+                y: position.y,
+            }),
+            WindowEvent::CursorLeft { .. } => Some(InputEvent::CursorLeft),
+            WindowEvent::MouseWheel { delta, .. } => Some(InputEvent::Scroll(match delta {
+                MouseScrollDelta::LineDelta(_, lines) => lines,
+                // roughly one line per 20 logical pixels
+                MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+            })),
+            WindowEvent::Focused(false) => Some(InputEvent::Unfocused),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BindingsError {
+    Io(io::Error),
+    // lines are counted from 1
+    Syntax { line: usize },
+    UnknownButton { line: usize, name: String },
+}
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BindingsError::Io(ref err) => write!(f, "{}", err),
+            BindingsError::Syntax { line } => {
+                write!(f, "line {} is not of the form `action = button`", line)
+            }
+            BindingsError::UnknownButton { line, ref name } => {
+                write!(f, "line {} binds unknown button {}", line, name)
+            }
+        }
+    }
+}
+
+impl Error for BindingsError {}
+
+#[derive(Clone, Debug, Default)]
+pub struct Bindings {
+    actions: HashMap<String, Vec<Button>>,
+}
+
+impl Bindings {
+    pub fn parse(text: &str) -> Result<Bindings, BindingsError> {
+        let mut bindings = Bindings::default();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let (action, buttons) = match (parts.next(), parts.next()) {
+                (Some(action), Some(buttons)) if !action.trim().is_empty() => {
+                    (action.trim(), buttons)
+                }
+                _ => return Err(BindingsError::Syntax { line: index + 1 }),
+            };
+
+            let buttons = buttons
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| {
+                    Button::from_name(name).ok_or_else(|| BindingsError::UnknownButton {
+                        line: index + 1,
+                        name: name.to_string(),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            bindings.actions.insert(action.to_string(), buttons);
+        }
+
+        Ok(bindings)
+    }
+
+    pub fn load(path: &Path) -> Result<Bindings, BindingsError> {
+        let text = fs::read_to_string(path).map_err(BindingsError::Io)?;
+        Bindings::parse(&text)
+    }
+
+    // actions bound in `other` replace the ones here, the rest are kept
+    pub fn merge(&mut self, other: Bindings) {
+        self.actions.extend(other.actions);
+    }
+
+    pub fn buttons(&self, action: &str) -> &[Button] {
+        self.actions
+            .get(action)
+            .map_or(&[], |buttons| buttons.as_slice())
+    }
+}
+
+#[derive(Default)]
+pub struct Input {
+    bindings: Bindings,
+    held: HashSet<Button>,
+    // changes since `begin_frame`
+    pressed: HashSet<Button>,
+    released: HashSet<Button>,
+    // in logical pixels, None while the cursor is outside of the window
+    cursor: Option<(f64, f64)>,
+    cursor_delta: (f64, f64),
+    // in lines
+    scroll: f32,
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Input {
+        Input {
+            bindings,
+            ..Input::default()
+        }
+    }
+
+    // call before polling the frame's events
+    pub fn begin_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.cursor_delta = (0.0, 0.0);
+        self.scroll = 0.0;
+    }
+
+    pub fn apply(&mut self, event: &InputEvent) {
+        match *event {
+            // held keys repeat, only the first press counts
+            InputEvent::Button {
+                button,
+                pressed: true,
+            } => {
+                if self.held.insert(button) {
+                    self.pressed.insert(button);
+                }
+            }
+            InputEvent::Button {
+                button,
+                pressed: false,
+            } => {
+                if self.held.remove(&button) {
+                    self.released.insert(button);
+                }
+            }
+            InputEvent::CursorMoved { x, y } => {
+                if let Some((last_x, last_y)) = self.cursor {
+                    self.cursor_delta.0 += x - last_x;
+                    self.cursor_delta.1 += y - last_y;
+                }
+                self.cursor = Some((x, y));
+            }
+            InputEvent::CursorLeft => self.cursor = None,
+            InputEvent::Scroll(lines) => self.scroll += lines,
+            // buttons let go while unfocused never reach us
+            InputEvent::Unfocused => {
+                self.released.extend(self.held.drain());
+            }
+        }
+    }
+
+    pub fn button_held(&self, button: Button) -> bool {
+        self.held.contains(&button)
+    }
+
+    pub fn button_pressed(&self, button: Button) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    pub fn button_released(&self, button: Button) -> bool {
+        self.released.contains(&button)
+    }
+
+    // actions are held while any of their buttons is, and pressed or released when any of them
+    // was this frame
+    pub fn action_held(&self, action: &str) -> bool {
+        self.bindings
+            .buttons(action)
+            .iter()
+            .any(|&button| self.button_held(button))
+    }
+
+    pub fn action_pressed(&self, action: &str) -> bool {
+        self.bindings
+            .buttons(action)
+            .iter()
+            .any(|&button| self.button_pressed(button))
+    }
+
+    pub fn action_released(&self, action: &str) -> bool {
+        self.bindings
+            .buttons(action)
+            .iter()
+            .any(|&button| self.button_released(button))
+    }
+
+    pub fn cursor(&self) -> Option<(f64, f64)> {
+        self.cursor
+    }
+
+    pub fn cursor_delta(&self) -> (f64, f64) {
+        self.cursor_delta
+    }
+
+    pub fn scroll(&self) -> f32 {
+        self.scroll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings_map_actions_to_buttons() {
+        let bindings =
+            Bindings::parse("# movement\n\nforward = W, Up\nfire = MouseLeft\nnothing =\n")
+                .unwrap();
+        assert_eq!(
+            bindings.buttons("forward"),
+            [
+                Button::Key(VirtualKeyCode::W),
+                Button::Key(VirtualKeyCode::Up)
+            ]
+        );
+        assert_eq!(bindings.buttons("fire"), [Button::Mouse(MouseButton::Left)]);
+        assert!(bindings.buttons("nothing").is_empty());
+        assert!(bindings.buttons("unbound").is_empty());
+    }
+
+    #[test]
+    fn bindings_without_an_action_are_rejected() {
+        for text in ["forward W", "forward = W\n = S"].iter() {
+            match Bindings::parse(text) {
+                Err(BindingsError::Syntax { line }) => assert_eq!(line, text.lines().count()),
+                Err(err) => panic!("unexpected error: {}", err),
+                Ok(_) => panic!("parsed invalid bindings {:?}", text),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_buttons_are_rejected() {
+        match Bindings::parse("forward = W\n\nfire = MouseLeft, Mouse4") {
+            Err(BindingsError::UnknownButton { line, ref name }) => {
+                assert_eq!(line, 3);
+                assert_eq!(name, "Mouse4");
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("parsed an unknown button"),
+        }
+    }
+}
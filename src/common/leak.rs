@@ -0,0 +1,97 @@
+// debug builds count the device objects created and destroyed through `resource::Owned` per kind
+// and remember where each live one was created, so anything still alive at shutdown can be
+// reported; release builds compile all of this down to nothing
+#[cfg(debug_assertions)]
+use backtrace::Backtrace;
+#[cfg(debug_assertions)]
+use std::cell::RefCell;
+#[cfg(debug_assertions)]
+use std::collections::{BTreeMap, HashMap};
+
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct KindCounts {
+    created: usize,
+    destroyed: usize,
+}
+
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct Tracker {
+    next_id: u64,
+    counts: BTreeMap<&'static str, KindCounts>,
+    live: HashMap<u64, (&'static str, Backtrace)>,
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    static TRACKER: RefCell<Tracker> = RefCell::new(Tracker::default());
+}
+
+// identifies one tracked object until it's untracked
+pub struct Token {
+    #[cfg(debug_assertions)]
+    id: u64,
+}
+
+#[cfg(debug_assertions)]
+pub fn track(kind: &'static str) -> Token {
+    TRACKER.with(|tracker| {
+        let mut tracker = tracker.borrow_mut();
+        let id = tracker.next_id;
+        tracker.next_id += 1;
+        tracker.counts.entry(kind).or_default().created += 1;
+        // symbols are only resolved for the backtraces that end up being reported
+        tracker.live.insert(id, (kind, Backtrace::new_unresolved()));
+        Token { id }
+    })
+}
+
+#[cfg(not(debug_assertions))]
+pub fn track(_kind: &'static str) -> Token {
+    Token {}
+}
+
+#[cfg(debug_assertions)]
+pub fn untrack(token: &Token) {
+    TRACKER.with(|tracker| {
+        let mut tracker = tracker.borrow_mut();
+        if let Some((kind, _)) = tracker.live.remove(&token.id) {
+            tracker.counts.entry(kind).or_default().destroyed += 1;
+        }
+    });
+}
+
+#[cfg(not(debug_assertions))]
+pub fn untrack(_token: &Token) {}
+
+// logs the counts per kind and a warning with the creation backtrace for every object that is
+// still alive, returning how many there are
+#[cfg(debug_assertions)]
+pub fn report() -> usize {
+    TRACKER.with(|tracker| {
+        let mut tracker = tracker.borrow_mut();
+
+        for (kind, counts) in tracker.counts.iter() {
+            info!(
+                "{}: {} created, {} destroyed",
+                kind, counts.created, counts.destroyed
+            );
+        }
+
+        let mut leaks: Vec<_> = tracker.live.drain().collect();
+        leaks.sort_by_key(|&(id, _)| id);
+        let count = leaks.len();
+        for (_, (kind, mut backtrace)) in leaks {
+            backtrace.resolve();
+            warn!("leaked {}, created at:\n{:?}", kind, backtrace);
+        }
+
+        count
+    })
+}
+
+#[cfg(not(debug_assertions))]
+pub fn report() -> usize {
+    0
+}
@@ -0,0 +1,481 @@
+// device memory is allocated from the driver in large blocks and carved up into sub-allocations,
+// drivers only guarantee a few thousand live allocations (`max_memory_allocation_count`)
+use back;
+use common::resource::{self, DeviceRef};
+use hal::{adapter, memory, Adapter, Backend, Device, MemoryTypeId, PhysicalDevice};
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+pub const DEFAULT_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MemoryError {
+    // none of the memory types allowed by the requirements has all of the requested properties
+    NoMemoryType {
+        type_mask: u64,
+        properties: memory::Properties,
+    },
+    OutOfMemory,
+    MapFailed,
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemoryError::NoMemoryType {
+                type_mask,
+                properties,
+            } => write!(
+                f,
+                "no memory type in mask {:#b} has properties {:?}",
+                type_mask, properties
+            ),
+            MemoryError::OutOfMemory => write!(f, "out of device memory"),
+            MemoryError::MapFailed => write!(f, "could not map host visible memory"),
+        }
+    }
+}
+
+impl Error for MemoryError {}
+
+// allocations with different strategies never share a block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    // short lived data such as staging copies, bump allocated; a block is reused from the start
+    // once all of its allocations have been freed
+    Linear,
+    // long lived resources, allocated first fit from a free list
+    FreeList,
+}
+
+// buffers and linear images must not share a `buffer_image_granularity` sized page with optimal
+// images, so when the granularity matters the two are kept in separate blocks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    Linear,
+    Optimal,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryStats {
+    // blocks, including dedicated allocations, and their size in bytes
+    pub blocks: usize,
+    pub reserved: u64,
+    // live sub-allocations and their size in bytes
+    pub allocations: usize,
+    pub used: u64,
+}
+
+impl MemoryStats {
+    fn add(&mut self, other: &MemoryStats) {
+        self.blocks += other.blocks;
+        self.reserved += other.reserved;
+        self.allocations += other.allocations;
+        self.used += other.used;
+    }
+}
+
+pub struct Allocation {
+    memory: Rc<resource::Memory>,
+    mapping: Option<*mut u8>,
+    memory_type: usize,
+    // pool and block index, None for dedicated allocations
+    block: Option<(usize, usize)>,
+    offset: u64,
+    size: u64,
+}
+
+impl Allocation {
+    pub fn memory(&self) -> &<back::Backend as Backend>::Memory {
+        &self.memory
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // start of the allocation if its memory is host visible, blocks stay mapped while they live
+    pub fn mapping(&self) -> Option<*mut u8> {
+        self.mapping
+    }
+}
+
+struct Block {
+    memory: Rc<resource::Memory>,
+    mapping: Option<*mut u8>,
+    size: u64,
+    // free ranges sorted by offset, for the free list strategy
+    free: Vec<Range<u64>>,
+    // next offset, for the linear strategy
+    cursor: u64,
+    // number of live allocations, the block is empty again once it reaches 0
+    live: usize,
+}
+
+struct Pool {
+    memory_type: usize,
+    strategy: Strategy,
+    kind: Option<ResourceKind>,
+    // allocations refer to their block by index, so freed blocks leave an empty slot behind
+    blocks: Vec<Option<Block>>,
+}
+
+pub struct MemoryAllocator {
+    device: DeviceRef,
+    memory_types: Vec<adapter::MemoryType>,
+    block_size: u64,
+    // resource kinds only need separate blocks if the granularity is coarser than any alignment
+    separate_kinds: bool,
+    pools: Vec<Pool>,
+    stats: Vec<MemoryStats>,
+}
+
+pub fn align_up(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) / alignment * alignment
+}
+
+fn allocate_from_free_list(free: &mut Vec<Range<u64>>, size: u64, alignment: u64) -> Option<u64> {
+    for i in 0..free.len() {
+        let range = free[i].clone();
+        let offset = align_up(range.start, alignment);
+        if offset + size <= range.end {
+            free.remove(i);
+            if offset + size < range.end {
+                free.insert(i, offset + size..range.end);
+            }
+            // padding in front stays available for allocations with smaller alignments
+            if range.start < offset {
+                free.insert(i, range.start..offset);
+            }
+            return Some(offset);
+        }
+    }
+
+    None
+}
+
+fn release_to_free_list(free: &mut Vec<Range<u64>>, range: Range<u64>) {
+    let i = free
+        .iter()
+        .position(|free_range| free_range.start > range.start)
+        .unwrap_or_else(|| free.len());
+    free.insert(i, range);
+
+    if i + 1 < free.len() && free[i].end == free[i + 1].start {
+        free[i].end = free[i + 1].end;
+        free.remove(i + 1);
+    }
+
+    if i > 0 && free[i - 1].end == free[i].start {
+        free[i - 1].end = free[i].end;
+        free.remove(i);
+    }
+}
+
+impl MemoryAllocator {
+    pub fn new(
+        adapter: &Adapter<back::Backend>,
+        device: &DeviceRef,
+        block_size: u64,
+    ) -> MemoryAllocator {
+        let memory_types = adapter.physical_device.memory_properties().memory_types;
+        let granularity = adapter.physical_device.limits().buffer_image_granularity;
+        let stats = vec![MemoryStats::default(); memory_types.len()];
+
+        MemoryAllocator {
+            device: device.clone(),
+            memory_types,
+            block_size,
+            separate_kinds: granularity > 1,
+            pools: Vec::new(),
+            stats,
+        }
+    }
+
+    // memory types are ordered by preference, so the first one that fits is the best one
+    pub fn find_memory_type(
+        &self,
+        type_mask: u64,
+        properties: memory::Properties,
+    ) -> Option<MemoryTypeId> {
+        self.memory_types
+            .iter()
+            .enumerate()
+            .position(|(id, memory_type)| {
+                type_mask & (1 << id) != 0 && memory_type.properties.contains(properties)
+            })
+            .map(MemoryTypeId)
+    }
+
+    pub unsafe fn allocate(
+        &mut self,
+        requirements: memory::Requirements,
+        properties: memory::Properties,
+        strategy: Strategy,
+        kind: ResourceKind,
+    ) -> Result<Allocation, MemoryError> {
+        let memory_type = self
+            .find_memory_type(requirements.type_mask, properties)
+            .ok_or(MemoryError::NoMemoryType {
+                type_mask: requirements.type_mask,
+                properties,
+            })?
+            .0;
+        let size = requirements.size;
+        let alignment = requirements.alignment.max(1);
+
+        // anything that would take up most of a block gets its own
+        let allocation = if size > self.block_size / 2 {
+            let block = self.allocate_block(memory_type, size)?;
+            self.stats[memory_type].blocks += 1;
+            self.stats[memory_type].reserved += size;
+            Allocation {
+                memory: block.memory,
+                mapping: block.mapping,
+                memory_type,
+                block: None,
+                offset: 0,
+                size,
+            }
+        } else {
+            let pool = self.find_pool(memory_type, strategy, kind);
+            let (block, offset) = match self.allocate_from_pool(pool, size, alignment) {
+                Some(block_and_offset) => block_and_offset,
+                None => {
+                    let block_size = self.block_size;
+                    let block = self.allocate_block(memory_type, block_size)?;
+                    self.stats[memory_type].blocks += 1;
+                    self.stats[memory_type].reserved += block_size;
+                    let blocks = &mut self.pools[pool].blocks;
+                    match blocks.iter().position(Option::is_none) {
+                        Some(slot) => blocks[slot] = Some(block),
+                        None => blocks.push(Some(block)),
+                    }
+                    self.allocate_from_pool(pool, size, alignment)
+                        .expect("allocation does not fit into an empty block")
+                }
+            };
+
+            let block_ref = self.pools[pool].blocks[block]
+                .as_ref()
+                .expect("allocated from a freed block!");
+            Allocation {
+                memory: block_ref.memory.clone(),
+                mapping: block_ref
+                    .mapping
+                    .map(|mapping| mapping.offset(offset as isize)),
+                memory_type,
+                block: Some((pool, block)),
+                offset,
+                size,
+            }
+        };
+
+        self.stats[memory_type].allocations += 1;
+        self.stats[memory_type].used += size;
+        Ok(allocation)
+    }
+
+    // the resources bound to the allocation must no longer be in use by the gpu
+    pub fn free(&mut self, allocation: Allocation) {
+        let stats = &mut self.stats[allocation.memory_type];
+        stats.allocations -= 1;
+        stats.used -= allocation.size;
+
+        match allocation.block {
+            Some((pool, index)) => {
+                let strategy = self.pools[pool].strategy;
+                let block = self.pools[pool].blocks[index]
+                    .as_mut()
+                    .expect("allocation was already freed with its block!");
+                block.live -= 1;
+                match strategy {
+                    Strategy::Linear => {
+                        if block.live == 0 {
+                            block.cursor = 0;
+                        }
+                    }
+                    Strategy::FreeList => release_to_free_list(
+                        &mut block.free,
+                        allocation.offset..allocation.offset + allocation.size,
+                    ),
+                }
+                if block.live == 0 {
+                    self.release_empty_block(pool, index);
+                }
+            }
+            // the memory is freed once the allocation is dropped
+            None => {
+                stats.blocks -= 1;
+                stats.reserved -= allocation.size;
+            }
+        }
+    }
+
+    // one empty block is kept per pool, so a resource that is freed and allocated again every
+    // so often doesn't allocate device memory every time; any other goes back to the device
+    // once the allocations referring to its memory are dropped
+    fn release_empty_block(&mut self, pool: usize, index: usize) {
+        let pool = &mut self.pools[pool];
+        let has_spare = pool.blocks.iter().enumerate().any(|(other, block)| {
+            other != index && block.as_ref().map_or(false, |block| block.live == 0)
+        });
+        if has_spare {
+            let block = pool.blocks[index].take().unwrap();
+            let stats = &mut self.stats[pool.memory_type];
+            stats.blocks -= 1;
+            stats.reserved -= block.size;
+        }
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        let mut total = MemoryStats::default();
+        for stats in self.stats.iter() {
+            total.add(stats);
+        }
+        total
+    }
+
+    pub fn log_stats(&self) {
+        for (id, stats) in self.stats.iter().enumerate() {
+            if stats.blocks > 0 {
+                info!(
+                    "memory type {} ({:?}): {} blocks, {} of {} bytes used by {} allocations",
+                    id,
+                    self.memory_types[id].properties,
+                    stats.blocks,
+                    stats.used,
+                    stats.reserved,
+                    stats.allocations
+                );
+            }
+        }
+    }
+
+    fn find_pool(&mut self, memory_type: usize, strategy: Strategy, kind: ResourceKind) -> usize {
+        let kind = if self.separate_kinds {
+            Some(kind)
+        } else {
+            None
+        };
+        match self.pools.iter().position(|pool| {
+            pool.memory_type == memory_type && pool.strategy == strategy && pool.kind == kind
+        }) {
+            Some(pool) => pool,
+            None => {
+                self.pools.push(Pool {
+                    memory_type,
+                    strategy,
+                    kind,
+                    blocks: Vec::new(),
+                });
+                self.pools.len() - 1
+            }
+        }
+    }
+
+    // returns the block index and offset within it
+    fn allocate_from_pool(
+        &mut self,
+        pool: usize,
+        size: u64,
+        alignment: u64,
+    ) -> Option<(usize, u64)> {
+        let pool = &mut self.pools[pool];
+        for (index, block) in pool.blocks.iter_mut().enumerate() {
+            let block = match *block {
+                Some(ref mut block) => block,
+                None => continue,
+            };
+            match pool.strategy {
+                Strategy::Linear => {
+                    let offset = align_up(block.cursor, alignment);
+                    if offset + size <= block.size {
+                        block.cursor = offset + size;
+                        block.live += 1;
+                        return Some((index, offset));
+                    }
+                }
+                Strategy::FreeList => {
+                    if let Some(offset) = allocate_from_free_list(&mut block.free, size, alignment)
+                    {
+                        block.live += 1;
+                        return Some((index, offset));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    unsafe fn allocate_block(&self, memory_type: usize, size: u64) -> Result<Block, MemoryError> {
+        let memory = self
+            .device
+            .allocate_memory(MemoryTypeId(memory_type), size)
+            .map_err(|_| MemoryError::OutOfMemory)?;
+        let memory = resource::memory(&self.device, memory);
+
+        // host visible blocks are mapped once for their whole lifetime, mapping the same memory
+        // twice isn't allowed and sub-allocations would otherwise have to share a mapping
+        let mapping = if self.memory_types[memory_type]
+            .properties
+            .contains(memory::Properties::CPU_VISIBLE)
+        {
+            Some(
+                self.device
+                    .map_memory(&memory, 0..size)
+                    .map_err(|_| MemoryError::MapFailed)?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Block {
+            memory: Rc::new(memory),
+            mapping,
+            size,
+            free: vec![0..size],
+            cursor: 0,
+            live: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_ranges_merge_with_their_neighbours() {
+        let mut free = vec![0..256];
+        assert_eq!(allocate_from_free_list(&mut free, 64, 1), Some(0));
+        assert_eq!(allocate_from_free_list(&mut free, 64, 1), Some(64));
+        assert_eq!(allocate_from_free_list(&mut free, 64, 1), Some(128));
+        assert_eq!(free, vec![192..256]);
+
+        release_to_free_list(&mut free, 0..64);
+        release_to_free_list(&mut free, 128..192);
+        assert_eq!(free, vec![0..64, 128..256]);
+        release_to_free_list(&mut free, 64..128);
+        assert_eq!(free, vec![0..256]);
+    }
+
+    #[test]
+    fn alignment_padding_stays_free() {
+        let mut free = vec![0..256];
+        assert_eq!(allocate_from_free_list(&mut free, 16, 1), Some(0));
+        assert_eq!(allocate_from_free_list(&mut free, 64, 64), Some(64));
+        assert_eq!(free, vec![16..64, 128..256]);
+
+        assert_eq!(allocate_from_free_list(&mut free, 32, 16), Some(16));
+        assert_eq!(free, vec![48..64, 128..256]);
+        assert_eq!(allocate_from_free_list(&mut free, 256, 1), None);
+    }
+}
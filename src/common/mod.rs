@@ -0,0 +1,20 @@
+// helpers shared by the later chapters, so the boilerplate from the earlier ones
+// doesn't need to be re-typed every time
+pub mod barrier;
+pub mod camera;
+pub mod frame_timer;
+pub mod input;
+pub mod leak;
+pub mod memory;
+pub mod pipeline;
+pub mod profile;
+pub mod query;
+pub mod render_graph;
+pub mod render_pass;
+pub mod replay;
+pub mod resource;
+pub mod screenshot;
+pub mod settings;
+pub mod timestep;
+pub mod upload;
+pub mod window_mode;
@@ -0,0 +1,296 @@
+use back;
+use common::resource::{self, DeviceRef};
+use glsl_to_spirv;
+use hal::{format, pass, pso, window, Backend, Device, Primitive};
+use std::io::Read;
+use std::ops::Range;
+
+pub fn compile_shader(source: &str, shader_type: glsl_to_spirv::ShaderType) -> Vec<u8> {
+    glsl_to_spirv::compile(source, shader_type)
+        .expect("Error compiling shader code.")
+        .bytes()
+        .map(|b| b.unwrap())
+        .collect::<Vec<u8>>()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    // source replaces destination
+    Replace,
+    // source over destination, weighted by source alpha
+    Alpha,
+    // source added to destination
+    Additive,
+}
+
+impl BlendMode {
+    fn blend_state(self) -> pso::BlendState {
+        let (src, dst) = match self {
+            BlendMode::Replace => (pso::Factor::One, pso::Factor::Zero),
+            BlendMode::Alpha => (pso::Factor::SrcAlpha, pso::Factor::OneMinusSrcAlpha),
+            BlendMode::Additive => (pso::Factor::One, pso::Factor::One),
+        };
+
+        pso::BlendState::On {
+            color: pso::BlendOp::Add { src, dst },
+            alpha: pso::BlendOp::Add {
+                src: pso::Factor::One,
+                dst: pso::Factor::Zero,
+            },
+        }
+    }
+}
+
+// fields are dropped in order, so the pipeline goes before the layouts it was created with
+pub struct Pipeline {
+    pub pipeline: resource::GraphicsPipeline,
+    pub layout: resource::PipelineLayout,
+    pub descriptor_set_layouts: Vec<resource::DescriptorSetLayout>,
+}
+
+// everything not exposed here (polygon mode, depth bias, multisampling...) keeps the
+// values the tutorial uses for its triangle
+pub struct PipelineBuilder<'a> {
+    vertex_shader: &'a [u8],
+    fragment_shader: Option<&'a [u8]>,
+    vertex_buffers: Vec<pso::VertexBufferDesc>,
+    attributes: Vec<pso::AttributeDesc>,
+    primitive: Primitive,
+    cull_face: pso::Face,
+    front_face: pso::FrontFace,
+    blend_mode: BlendMode,
+    depth_test: pso::DepthTest,
+    viewport: Option<window::Extent2D>,
+    bindings: Vec<pso::DescriptorSetLayoutBinding>,
+    push_constants: Vec<(pso::ShaderStageFlags, Range<u32>)>,
+}
+
+impl<'a> PipelineBuilder<'a> {
+    // shaders are passed as compiled SPIR-V
+    pub fn new(vertex_shader: &'a [u8], fragment_shader: &'a [u8]) -> PipelineBuilder<'a> {
+        PipelineBuilder {
+            vertex_shader,
+            fragment_shader: Some(fragment_shader),
+            vertex_buffers: Vec::new(),
+            attributes: Vec::new(),
+            primitive: Primitive::TriangleList,
+            cull_face: pso::Face::BACK,
+            front_face: pso::FrontFace::Clockwise,
+            blend_mode: BlendMode::Replace,
+            depth_test: pso::DepthTest::Off,
+            viewport: None,
+            bindings: Vec::new(),
+            push_constants: Vec::new(),
+        }
+    }
+
+    // for depth-only passes
+    pub fn without_fragment_shader(mut self) -> Self {
+        self.fragment_shader = None;
+        self
+    }
+
+    pub fn vertex_buffer(mut self, stride: u32, rate: pso::InstanceRate) -> Self {
+        let binding = self.vertex_buffers.len() as u32;
+        self.vertex_buffers.push(pso::VertexBufferDesc {
+            binding,
+            stride,
+            rate,
+        });
+        self
+    }
+
+    // attributes refer to the most recently added vertex buffer
+    pub fn attribute(mut self, location: u32, format: format::Format, offset: u32) -> Self {
+        let binding = self
+            .vertex_buffers
+            .len()
+            .checked_sub(1)
+            .expect("attribute added before any vertex buffer!") as u32;
+        self.attributes.push(pso::AttributeDesc {
+            location,
+            binding,
+            element: pso::Element { format, offset },
+        });
+        self
+    }
+
+    pub fn primitive(mut self, primitive: Primitive) -> Self {
+        self.primitive = primitive;
+        self
+    }
+
+    pub fn cull_face(mut self, cull_face: pso::Face) -> Self {
+        self.cull_face = cull_face;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: pso::FrontFace) -> Self {
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn blend_mode(mut self, blend_mode: BlendMode) -> Self {
+        self.blend_mode = blend_mode;
+        self
+    }
+
+    pub fn depth_test(mut self, depth_test: pso::DepthTest) -> Self {
+        self.depth_test = depth_test;
+        self
+    }
+
+    // bakes a viewport and scissor covering the whole extent into the pipeline
+    pub fn viewport(mut self, extent: window::Extent2D) -> Self {
+        self.viewport = Some(extent);
+        self
+    }
+
+    pub fn descriptor_binding(mut self, binding: pso::DescriptorSetLayoutBinding) -> Self {
+        self.bindings.push(binding);
+        self
+    }
+
+    pub fn push_constants(mut self, stages: pso::ShaderStageFlags, range: Range<u32>) -> Self {
+        self.push_constants.push((stages, range));
+        self
+    }
+
+    pub unsafe fn build(
+        self,
+        device: &DeviceRef,
+        subpass: pass::Subpass<back::Backend>,
+        pipeline_cache: Option<&<back::Backend as Backend>::PipelineCache>,
+    ) -> Pipeline {
+        let vert_shader_module = resource::shader_module(
+            device,
+            device
+                .create_shader_module(self.vertex_shader)
+                .expect("Error creating shader module."),
+        );
+        let frag_shader_module = self.fragment_shader.map(|fragment_shader| {
+            resource::shader_module(
+                device,
+                device
+                    .create_shader_module(fragment_shader)
+                    .expect("Error creating fragment module."),
+            )
+        });
+
+        let descriptor_set_layouts = vec![resource::descriptor_set_layout(
+            device,
+            device
+                .create_descriptor_set_layout(
+                    self.bindings,
+                    Vec::<<back::Backend as Backend>::Sampler>::new(),
+                )
+                .unwrap(),
+        )];
+        let layout = resource::pipeline_layout(
+            device,
+            device
+                .create_pipeline_layout(
+                    descriptor_set_layouts.iter().map(|layout| &**layout),
+                    self.push_constants,
+                )
+                .unwrap(),
+        );
+
+        let pipeline = {
+            let shaders = pso::GraphicsShaderSet {
+                vertex: pso::EntryPoint::<back::Backend> {
+                    entry: "main",
+                    module: &*vert_shader_module,
+                    specialization: pso::Specialization {
+                        constants: &[],
+                        data: &[],
+                    },
+                },
+                hull: None,
+                domain: None,
+                geometry: None,
+                fragment: frag_shader_module.as_ref().map(|module| pso::EntryPoint::<
+                    back::Backend,
+                > {
+                    entry: "main",
+                    module: &**module,
+                    specialization: pso::Specialization {
+                        constants: &[],
+                        data: &[],
+                    },
+                }),
+            };
+
+            let rasterizer = pso::Rasterizer {
+                depth_clamping: false,
+                polygon_mode: pso::PolygonMode::Fill,
+                cull_face: self.cull_face,
+                front_face: self.front_face,
+                depth_bias: None,
+                conservative: false,
+            };
+
+            let blender = pso::BlendDesc {
+                // an enabled logic op replaces blending, so the blend mode would be ignored
+                logic_op: None,
+                targets: vec![pso::ColorBlendDesc(
+                    pso::ColorMask::ALL,
+                    self.blend_mode.blend_state(),
+                )],
+            };
+
+            let depth_stencil = pso::DepthStencilDesc {
+                depth: self.depth_test,
+                depth_bounds: false,
+                stencil: pso::StencilTest::Off,
+            };
+
+            let rect = self.viewport.map(|extent| pso::Rect {
+                x: 0,
+                y: 0,
+                w: extent.width as i16,
+                h: extent.height as i16,
+            });
+
+            let baked_states = pso::BakedStates {
+                viewport: rect.map(|rect| pso::Viewport {
+                    rect,
+                    depth: (0.0..1.0),
+                }),
+                scissor: rect,
+                blend_color: None,
+                depth_bounds: None,
+            };
+
+            let desc = pso::GraphicsPipelineDesc {
+                shaders,
+                rasterizer,
+                vertex_buffers: self.vertex_buffers,
+                attributes: self.attributes,
+                input_assembler: pso::InputAssemblerDesc::new(self.primitive),
+                blender,
+                depth_stencil,
+                multisampling: None,
+                baked_states,
+                layout: &*layout,
+                subpass,
+                flags: pso::PipelineCreationFlags::empty(),
+                parent: pso::BasePipeline::None,
+            };
+
+            resource::graphics_pipeline(
+                device,
+                device
+                    .create_graphics_pipeline(&desc, pipeline_cache)
+                    .expect("failed to create graphics pipeline!"),
+            )
+        };
+
+        // shader modules are dropped here, they aren't needed once the pipeline exists
+        Pipeline {
+            descriptor_set_layouts,
+            layout,
+            pipeline,
+        }
+    }
+}
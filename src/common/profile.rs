@@ -0,0 +1,176 @@
+// scoped cpu spans plus gpu spans from timestamp queries, collected per thread and written out
+// in chrome's trace event format (load the file in chrome://tracing or https://ui.perfetto.dev)
+use common::timestep;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const CPU_TRACK: u32 = 1;
+const GPU_TRACK: u32 = 2;
+
+// about 50MB of events, later ones are dropped so long `--trace` runs don't grow without limit
+const MAX_EVENTS: usize = 1_000_000;
+
+struct TraceEvent {
+    name: &'static str,
+    track: u32,
+    start: Duration,
+    duration: Duration,
+}
+
+struct Profiler {
+    epoch: Instant,
+    events: Vec<TraceEvent>,
+    // events past `MAX_EVENTS`
+    dropped: usize,
+}
+
+thread_local! {
+    static PROFILER: RefCell<Option<Profiler>> = RefCell::new(None);
+}
+
+// spans are only recorded after this is called, otherwise they cost next to nothing
+pub fn enable() {
+    PROFILER.with(|profiler| {
+        *profiler.borrow_mut() = Some(Profiler {
+            epoch: Instant::now(),
+            events: Vec::new(),
+            dropped: 0,
+        })
+    });
+}
+
+fn record(name: &'static str, track: u32, start: Instant, duration: Duration) {
+    PROFILER.with(|profiler| {
+        if let Some(ref mut profiler) = *profiler.borrow_mut() {
+            if profiler.events.len() >= MAX_EVENTS {
+                if profiler.dropped == 0 {
+                    warn!("trace is full, later spans are dropped");
+                }
+                profiler.dropped += 1;
+                return;
+            }
+            // gpu spans may be placed before the profiler was enabled
+            let start = if start > profiler.epoch {
+                start - profiler.epoch
+            } else {
+                Duration::new(0, 0)
+            };
+            profiler.events.push(TraceEvent {
+                name,
+                track,
+                start,
+                duration,
+            });
+        }
+    });
+}
+
+pub struct Span {
+    name: &'static str,
+    start: Instant,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        record(self.name, CPU_TRACK, self.start, self.start.elapsed());
+    }
+}
+
+// records the time until the returned guard is dropped
+pub fn span(name: &'static str) -> Span {
+    Span {
+        name,
+        start: Instant::now(),
+    }
+}
+
+pub fn scope<T, F: FnOnce() -> T>(name: &'static str, f: F) -> T {
+    let _span = span(name);
+    f()
+}
+
+// `start` is the gpu timestamp converted to the cpu clock, so both tracks line up
+pub fn gpu_span(name: &'static str, start: Instant, duration: Duration) {
+    record(name, GPU_TRACK, start, duration);
+}
+
+fn write_event<W: Write>(writer: &mut W, event: &TraceEvent) -> io::Result<()> {
+    write!(
+        writer,
+        ",\n{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}}}",
+        event.name.replace('\\', "\\\\").replace('"', "\\\""),
+        if event.track == GPU_TRACK { "gpu" } else { "cpu" },
+        event.track,
+        timestep::as_secs(event.start) * 1e6,
+        timestep::as_secs(event.duration) * 1e6
+    )
+}
+
+// does nothing if profiling was never enabled
+pub fn write_trace(path: &Path) -> io::Result<()> {
+    PROFILER.with(|profiler| {
+        let profiler = profiler.borrow();
+        let profiler = match *profiler {
+            Some(ref profiler) => profiler,
+            None => return Ok(()),
+        };
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        // the track names come first, so every event after them is preceded by a comma
+        write!(
+            writer,
+            "{{\"traceEvents\":[\n\
+             {{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"cpu\"}}}},\n\
+             {{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"gpu\"}}}}",
+            CPU_TRACK, GPU_TRACK
+        )?;
+
+        for event in profiler.events.iter() {
+            write_event(&mut writer, event)?;
+        }
+
+        writeln!(writer, "\n],\"displayTimeUnit\":\"ms\"}}")?;
+        if profiler.dropped > 0 {
+            warn!(
+                "trace only has the first {} spans, {} were dropped",
+                MAX_EVENTS, profiler.dropped
+            );
+        }
+        writer.flush()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_capped() {
+        enable();
+        let start = Instant::now();
+        for _ in 0..MAX_EVENTS + 3 {
+            gpu_span("span", start, Duration::new(0, 1));
+        }
+        PROFILER.with(|profiler| {
+            let profiler = profiler.borrow();
+            let profiler = profiler.as_ref().unwrap();
+            assert_eq!(profiler.events.len(), MAX_EVENTS);
+            assert_eq!(profiler.dropped, 3);
+        });
+    }
+
+    #[test]
+    fn spans_before_the_epoch_start_at_zero() {
+        let before = Instant::now();
+        enable();
+        gpu_span("span", before, Duration::new(0, 1));
+        PROFILER.with(|profiler| {
+            let profiler = profiler.borrow();
+            let profiler = profiler.as_ref().unwrap();
+            assert_eq!(profiler.events[0].start, Duration::new(0, 0));
+        });
+    }
+}
@@ -0,0 +1,372 @@
+use back;
+use common::resource::{self, DeviceRef};
+use hal::{
+    command, pso, query, queue, Adapter, Backend, Device, Features, Graphics, PhysicalDevice,
+};
+use std::mem;
+use std::time::{Duration, Instant};
+
+pub type FrameCommandBuffer =
+    command::CommandBuffer<back::Backend, Graphics, command::OneShot, command::Primary>;
+
+// reads back the 64-bit results of queries `0..count`, each of which writes `values_per_query`
+// of them (e.g. one per pipeline statistic), or None if any of them isn't available yet
+unsafe fn read_results(
+    device: &<back::Backend as Backend>::Device,
+    pool: &<back::Backend as Backend>::QueryPool,
+    count: query::Id,
+    values_per_query: usize,
+) -> Option<Vec<u64>> {
+    let mut results = vec![0u64; count as usize * values_per_query];
+    let stride = values_per_query * mem::size_of::<u64>();
+    let data = ::std::slice::from_raw_parts_mut(
+        results.as_mut_ptr() as *mut u8,
+        results.len() * mem::size_of::<u64>(),
+    );
+
+    match device.get_query_pool_results(
+        pool,
+        0..count,
+        data,
+        stride as _,
+        query::ResultFlags::BITS_64,
+    ) {
+        Ok(true) => Some(results),
+        _ => None,
+    }
+}
+
+// writes a single timestamp and pairs it with the cpu time halfway between submitting it and its
+// fence signalling, which is as close as the cpu can tell when the gpu wrote it
+unsafe fn calibrate(
+    device: &DeviceRef,
+    queue: &mut queue::CommandQueue<back::Backend, Graphics>,
+    command_pool: &mut resource::CommandPool,
+    pool: &<back::Backend as Backend>::QueryPool,
+) -> Option<(Instant, u64)> {
+    let fence = resource::fence(device, device.create_fence(false).ok()?);
+    let mut command_buffer: FrameCommandBuffer = command_pool.acquire_command_buffer();
+    command_buffer.begin();
+    command_buffer.reset_query_pool(pool, 0..1);
+    command_buffer.write_timestamp(
+        pso::PipelineStage::TOP_OF_PIPE,
+        query::Query { pool, id: 0 },
+    );
+    command_buffer.finish();
+
+    let submission = queue::Submission {
+        command_buffers: ::std::slice::from_ref(&command_buffer),
+        wait_semaphores: Vec::<(&<back::Backend as Backend>::Semaphore, pso::PipelineStage)>::new(),
+        signal_semaphores: Vec::<&<back::Backend as Backend>::Semaphore>::new(),
+    };
+    let submitted = Instant::now();
+    queue.submit(submission, Some(&*fence));
+    let waited = device.wait_for_fence(&fence, ::std::u64::MAX);
+    let signalled = Instant::now();
+    command_pool.free(Some(command_buffer));
+    if !waited.unwrap_or(false) {
+        return None;
+    }
+
+    let results = read_results(device, pool, 1, 1)?;
+    if results[0] == 0 {
+        return None;
+    }
+    Some((submitted + (signalled - submitted) / 2, results[0]))
+}
+
+// a pair of timestamps around the render pass, with one query pool per frame in flight so
+// results are only read once the frame's fence has signalled
+pub struct TimestampQueries {
+    pools: Vec<resource::QueryPool>,
+    // whether the pool for a frame has been written since it was last read
+    pending: Vec<bool>,
+    // nanoseconds per timestamp tick
+    period: f32,
+    // a cpu time and the gpu timestamp taken at (about) the same moment, to place gpu timestamps
+    // on the cpu clock
+    calibration: (Instant, u64),
+    // cleared once the queue turns out to write timestamps that can't be real
+    valid: bool,
+}
+
+impl TimestampQueries {
+    // returns None if timestamps aren't supported, in which case gpu timings are just missing.
+    // waits for the queue to calibrate the gpu clock, so only for when the device is created
+    pub unsafe fn new(
+        adapter: &Adapter<back::Backend>,
+        device: &DeviceRef,
+        queue: &mut queue::CommandQueue<back::Backend, Graphics>,
+        command_pool: &mut resource::CommandPool,
+        frames_in_flight: usize,
+    ) -> Option<TimestampQueries> {
+        // the period only says how long a tick is on the device; whether the queue family writes
+        // timestamps at all (vulkan's `timestamp_valid_bits`) isn't exposed by hal, so besides a
+        // zero period or a failed pool creation the only way to find out is checking what the
+        // calibration and `read` get back
+        let period = adapter.physical_device.limits().timestamp_period;
+        if period <= 0.0 {
+            info!("timestamp queries are not supported, gpu timings disabled");
+            return None;
+        }
+
+        let mut pools = Vec::with_capacity(frames_in_flight);
+        for _ in 0..frames_in_flight {
+            match device.create_query_pool(query::Type::Timestamp, 2) {
+                Ok(pool) => pools.push(resource::query_pool(device, pool)),
+                Err(_) => {
+                    info!("could not create timestamp query pool, gpu timings disabled");
+                    return None;
+                }
+            }
+        }
+
+        let calibration = match calibrate(device, queue, command_pool, &pools[0]) {
+            Some(calibration) => calibration,
+            None => {
+                info!("could not calibrate the gpu clock, gpu timings disabled");
+                return None;
+            }
+        };
+
+        Some(TimestampQueries {
+            pools,
+            pending: vec![false; frames_in_flight],
+            period,
+            calibration,
+            valid: true,
+        })
+    }
+
+    fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        let nanos = (ticks as f64 * f64::from(self.period)) as u64;
+        Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+    }
+
+    // must be recorded outside of a render pass
+    pub unsafe fn begin(&mut self, command_buffer: &mut FrameCommandBuffer, frame: usize) {
+        if !self.valid {
+            return;
+        }
+        let pool = &*self.pools[frame];
+        command_buffer.reset_query_pool(pool, 0..2);
+        command_buffer.write_timestamp(
+            pso::PipelineStage::TOP_OF_PIPE,
+            query::Query { pool, id: 0 },
+        );
+    }
+
+    pub unsafe fn end(&mut self, command_buffer: &mut FrameCommandBuffer, frame: usize) {
+        if !self.valid {
+            return;
+        }
+        command_buffer.write_timestamp(
+            pso::PipelineStage::BOTTOM_OF_PIPE,
+            query::Query {
+                pool: &self.pools[frame],
+                id: 1,
+            },
+        );
+        self.pending[frame] = true;
+    }
+
+    // when the render pass started on the cpu clock and how long it took. only call once the
+    // fence of the frame that wrote the queries has signalled
+    pub unsafe fn read(
+        &mut self,
+        device: &<back::Backend as Backend>::Device,
+        frame: usize,
+    ) -> Option<(Instant, Duration)> {
+        if !mem::replace(&mut self.pending[frame], false) {
+            return None;
+        }
+
+        let results = read_results(device, &self.pools[frame], 2, 1)?;
+        // a family without timestamp support leaves the queries zero (or undefined), which would
+        // otherwise show up as made up gpu times
+        if results[0] == 0 || results[1] <= results[0] {
+            info!("the queue does not write valid timestamps, gpu timings disabled");
+            self.valid = false;
+            return None;
+        }
+        let (cpu_origin, gpu_origin) = self.calibration;
+        let start = if results[0] >= gpu_origin {
+            cpu_origin + self.ticks_to_duration(results[0] - gpu_origin)
+        } else {
+            cpu_origin - self.ticks_to_duration(gpu_origin - results[0])
+        };
+        Some((start, self.ticks_to_duration(results[1] - results[0])))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DrawStatistics {
+    // samples that passed depth and stencil testing
+    pub samples_passed: u64,
+    // only available with the pipeline statistics query feature
+    pub vertex_shader_invocations: Option<u64>,
+    pub fragment_shader_invocations: Option<u64>,
+}
+
+// occlusion and (if supported) pipeline statistics queries for up to `capacity` draws a frame,
+// one set of pools per frame in flight
+pub struct DrawQueries {
+    occlusion_pools: Vec<resource::QueryPool>,
+    statistics_pools: Option<Vec<resource::QueryPool>>,
+    control_flags: query::ControlFlags,
+    capacity: query::Id,
+    // draws queried in each frame since its pools were last read
+    draw_counts: Vec<query::Id>,
+}
+
+impl DrawQueries {
+    // the device has to be opened with `DrawQueries::features` for precise counts and
+    // statistics; without them occlusion results may only be zero or non-zero
+    pub fn features(adapter: &Adapter<back::Backend>) -> Features {
+        adapter.physical_device.features()
+            & (Features::PRECISE_OCCLUSION_QUERY | Features::PIPELINE_STATISTICS_QUERY)
+    }
+
+    pub unsafe fn new(
+        device: &DeviceRef,
+        features: Features,
+        frames_in_flight: usize,
+        capacity: query::Id,
+    ) -> DrawQueries {
+        let occlusion_pools = (0..frames_in_flight)
+            .map(|_| {
+                let pool = device
+                    .create_query_pool(query::Type::Occlusion, capacity)
+                    .expect("failed to create occlusion query pool!");
+                resource::query_pool(device, pool)
+            })
+            .collect();
+
+        let statistics_pools = if features.contains(Features::PIPELINE_STATISTICS_QUERY) {
+            Some(
+                (0..frames_in_flight)
+                    .map(|_| {
+                        let pool = device
+                            .create_query_pool(
+                                query::Type::PipelineStatistics(
+                                    query::PipelineStatistic::VERTEX_SHADER_INVOCATIONS
+                                        | query::PipelineStatistic::FRAGMENT_SHADER_INVOCATIONS,
+                                ),
+                                capacity,
+                            )
+                            .expect("failed to create pipeline statistics query pool!");
+                        resource::query_pool(device, pool)
+                    })
+                    .collect(),
+            )
+        } else {
+            info!("pipeline statistics queries are not supported, only counting samples");
+            None
+        };
+
+        let control_flags = if features.contains(Features::PRECISE_OCCLUSION_QUERY) {
+            query::ControlFlags::PRECISE
+        } else {
+            query::ControlFlags::empty()
+        };
+
+        DrawQueries {
+            occlusion_pools,
+            statistics_pools,
+            control_flags,
+            capacity,
+            draw_counts: vec![0; frames_in_flight],
+        }
+    }
+
+    // must be recorded outside of a render pass, before any draw is queried
+    pub unsafe fn reset(&mut self, command_buffer: &mut FrameCommandBuffer, frame: usize) {
+        command_buffer.reset_query_pool(&self.occlusion_pools[frame], 0..self.capacity);
+        if let Some(ref statistics_pools) = self.statistics_pools {
+            command_buffer.reset_query_pool(&statistics_pools[frame], 0..self.capacity);
+        }
+        self.draw_counts[frame] = 0;
+    }
+
+    // wraps the draws recorded by `record_draw` in queries; results come back in the same order
+    pub unsafe fn query_draw<F>(
+        &mut self,
+        encoder: &mut command::RenderPassInlineEncoder<back::Backend>,
+        frame: usize,
+        record_draw: F,
+    ) where
+        F: FnOnce(&mut command::RenderPassInlineEncoder<back::Backend>),
+    {
+        let id = self.draw_counts[frame];
+        assert!(
+            id < self.capacity,
+            "more draws queried than the pools hold!"
+        );
+
+        let occlusion_pool = &*self.occlusion_pools[frame];
+        let statistics_pool = self
+            .statistics_pools
+            .as_ref()
+            .map(|statistics_pools| &*statistics_pools[frame]);
+
+        encoder.begin_query(
+            query::Query {
+                pool: occlusion_pool,
+                id,
+            },
+            self.control_flags,
+        );
+        if let Some(pool) = statistics_pool {
+            encoder.begin_query(query::Query { pool, id }, query::ControlFlags::empty());
+        }
+
+        record_draw(encoder);
+
+        if let Some(pool) = statistics_pool {
+            encoder.end_query(query::Query { pool, id });
+        }
+        encoder.end_query(query::Query {
+            pool: occlusion_pool,
+            id,
+        });
+
+        self.draw_counts[frame] += 1;
+    }
+
+    // only call once the fence of the frame that wrote the queries has signalled
+    pub unsafe fn read(
+        &mut self,
+        device: &<back::Backend as Backend>::Device,
+        frame: usize,
+    ) -> Option<Vec<DrawStatistics>> {
+        let count = mem::replace(&mut self.draw_counts[frame], 0);
+        if count == 0 {
+            return None;
+        }
+
+        let samples = read_results(device, &self.occlusion_pools[frame], count, 1)?;
+        // both statistics are written for every query, in the order of their flag bits
+        let invocations = match self.statistics_pools {
+            Some(ref statistics_pools) => {
+                Some(read_results(device, &statistics_pools[frame], count, 2)?)
+            }
+            None => None,
+        };
+
+        Some(
+            samples
+                .iter()
+                .enumerate()
+                .map(|(draw, &samples_passed)| DrawStatistics {
+                    samples_passed,
+                    vertex_shader_invocations: invocations
+                        .as_ref()
+                        .map(|invocations| invocations[draw * 2]),
+                    fragment_shader_invocations: invocations
+                        .as_ref()
+                        .map(|invocations| invocations[draw * 2 + 1]),
+                })
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,1442 @@
+// passes declare which named resources they read and write, compiling the graph orders them,
+// drops passes that don't contribute to the presented image, decides load/store ops and aliases
+// transient images with disjoint lifetimes; building it creates the render passes, transient
+// resources and framebuffers, and recording inserts the barriers between passes
+//
+// compiling only looks at the declarations, so the result can be inspected without a device
+use back;
+use common::barrier::{Barriers, ImageId, ImageState, ImageTracker};
+use common::memory::{Allocation, MemoryAllocator, ResourceKind, Strategy};
+use common::query::FrameCommandBuffer;
+use common::render_pass::{RenderPassBuilder, RenderPassError, SubpassBuilder};
+use common::resource::{self, DeviceRef};
+use hal::memory::Properties;
+use hal::{buffer, command, format, image, pass, pso, window, Backend, Device};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub type ResourceId = usize;
+pub type PassId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResourceDesc {
+    // image the size of the swapchain, only alive while the frame is rendered
+    Image {
+        format: format::Format,
+    },
+    Buffer {
+        size: u64,
+    },
+    // the swapchain image, presented after the last pass, or an image the size of the swapchain
+    // that's left to be copied from when rendering offscreen
+    Backbuffer {
+        format: format::Format,
+        presented: bool,
+    },
+}
+
+impl ResourceDesc {
+    // how the graph leaves a backbuffer after the last pass, and so finds it before the first
+    fn final_usage(&self) -> Option<Usage> {
+        match *self {
+            ResourceDesc::Backbuffer {
+                presented: true, ..
+            } => Some(Usage::Present),
+            ResourceDesc::Backbuffer {
+                presented: false, ..
+            } => Some(Usage::CopySource),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Usage {
+    Color { clear: bool },
+    DepthStencil { clear: bool },
+    Sampled,
+    BufferRead,
+    BufferWrite,
+    // only used by the graph itself, for the backbuffer between frames
+    Present,
+    CopySource,
+}
+
+impl Usage {
+    pub fn writes(&self) -> bool {
+        match *self {
+            Usage::Color { .. } | Usage::DepthStencil { .. } | Usage::BufferWrite => true,
+            Usage::Sampled | Usage::BufferRead | Usage::Present | Usage::CopySource => false,
+        }
+    }
+
+    fn clears(&self) -> bool {
+        match *self {
+            Usage::Color { clear } | Usage::DepthStencil { clear } => clear,
+            _ => false,
+        }
+    }
+
+    fn is_buffer(&self) -> bool {
+        match *self {
+            Usage::BufferRead | Usage::BufferWrite => true,
+            _ => false,
+        }
+    }
+
+    pub fn layout(&self) -> image::Layout {
+        match *self {
+            Usage::Color { .. } => image::Layout::ColorAttachmentOptimal,
+            Usage::DepthStencil { .. } => image::Layout::DepthStencilAttachmentOptimal,
+            Usage::Sampled => image::Layout::ShaderReadOnlyOptimal,
+            Usage::Present => image::Layout::Present,
+            Usage::CopySource => image::Layout::TransferSrcOptimal,
+            Usage::BufferRead | Usage::BufferWrite => image::Layout::General,
+        }
+    }
+
+    pub fn image_state(&self) -> ImageState {
+        let (stages, access) = match *self {
+            Usage::Color { .. } => (
+                pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+                image::Access::COLOR_ATTACHMENT_READ | image::Access::COLOR_ATTACHMENT_WRITE,
+            ),
+            Usage::DepthStencil { .. } => (
+                pso::PipelineStage::EARLY_FRAGMENT_TESTS | pso::PipelineStage::LATE_FRAGMENT_TESTS,
+                image::Access::DEPTH_STENCIL_ATTACHMENT_READ
+                    | image::Access::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            Usage::Sampled => (
+                pso::PipelineStage::FRAGMENT_SHADER,
+                image::Access::SHADER_READ,
+            ),
+            Usage::CopySource => (pso::PipelineStage::TRANSFER, image::Access::TRANSFER_READ),
+            // the acquire semaphore is waited on at color attachment output, so barriers on the
+            // backbuffer have to start there to wait for it
+            Usage::Present | Usage::BufferRead | Usage::BufferWrite => (
+                pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+                image::Access::empty(),
+            ),
+        };
+        ImageState::new(stages, access, self.layout())
+    }
+
+    fn buffer_state(&self) -> (pso::PipelineStage, buffer::Access) {
+        let stages = pso::PipelineStage::VERTEX_SHADER | pso::PipelineStage::FRAGMENT_SHADER;
+        match *self {
+            Usage::BufferRead => (stages, buffer::Access::SHADER_READ),
+            Usage::BufferWrite => (stages, buffer::Access::SHADER_WRITE),
+            _ => (stages, buffer::Access::empty()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderGraphError {
+    UnknownResource { pass: String, resource: ResourceId },
+    // image used as a buffer or the other way around
+    WrongResourceType { pass: String, resource: String },
+    DuplicateUse { pass: String, resource: String },
+    // resource is read but no pass ever writes it
+    NeverWritten { pass: String, resource: String },
+    Cycle,
+    // nothing writes the backbuffer, so every pass would be culled
+    NothingPresented,
+}
+
+impl fmt::Display for RenderGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenderGraphError::UnknownResource { ref pass, resource } => write!(
+                f,
+                "pass {} uses resource {} which does not exist",
+                pass, resource
+            ),
+            RenderGraphError::WrongResourceType {
+                ref pass,
+                ref resource,
+            } => write!(
+                f,
+                "pass {} uses {} as the wrong kind of resource",
+                pass, resource
+            ),
+            RenderGraphError::DuplicateUse {
+                ref pass,
+                ref resource,
+            } => write!(f, "pass {} uses {} more than once", pass, resource),
+            RenderGraphError::NeverWritten {
+                ref pass,
+                ref resource,
+            } => write!(f, "pass {} reads {} which is never written", pass, resource),
+            RenderGraphError::Cycle => write!(f, "render graph passes depend on each other"),
+            RenderGraphError::NothingPresented => write!(f, "no pass writes the backbuffer"),
+        }
+    }
+}
+
+impl Error for RenderGraphError {}
+
+#[derive(Clone, Debug)]
+pub struct PassBuilder {
+    name: String,
+    uses: Vec<(ResourceId, Usage)>,
+}
+
+impl PassBuilder {
+    pub fn new(name: &str) -> PassBuilder {
+        PassBuilder {
+            name: name.to_string(),
+            uses: Vec::new(),
+        }
+    }
+
+    // color and depth attachments are bound in the order they are added
+    pub fn color(mut self, resource: ResourceId) -> Self {
+        self.uses.push((resource, Usage::Color { clear: false }));
+        self
+    }
+
+    pub fn clear_color(mut self, resource: ResourceId) -> Self {
+        self.uses.push((resource, Usage::Color { clear: true }));
+        self
+    }
+
+    pub fn depth_stencil(mut self, resource: ResourceId) -> Self {
+        self.uses
+            .push((resource, Usage::DepthStencil { clear: false }));
+        self
+    }
+
+    pub fn clear_depth_stencil(mut self, resource: ResourceId) -> Self {
+        self.uses
+            .push((resource, Usage::DepthStencil { clear: true }));
+        self
+    }
+
+    pub fn sampled(mut self, resource: ResourceId) -> Self {
+        self.uses.push((resource, Usage::Sampled));
+        self
+    }
+
+    pub fn read_buffer(mut self, resource: ResourceId) -> Self {
+        self.uses.push((resource, Usage::BufferRead));
+        self
+    }
+
+    pub fn write_buffer(mut self, resource: ResourceId) -> Self {
+        self.uses.push((resource, Usage::BufferWrite));
+        self
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RenderGraphBuilder {
+    resources: Vec<(String, ResourceDesc)>,
+    passes: Vec<PassBuilder>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompiledResource {
+    pub name: String,
+    pub desc: ResourceDesc,
+    // index of the transient image or buffer backing this resource, None for the backbuffer
+    pub physical: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompiledAttachment {
+    pub resource: ResourceId,
+    pub ops: pass::AttachmentOps,
+    pub layout: image::Layout,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompiledBarrier {
+    pub resource: ResourceId,
+    // the first use in a frame waits for the last use in the previous one, and doesn't keep the
+    // contents unless it loads them
+    pub from: Usage,
+    pub to: Usage,
+    pub discard: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompiledPass {
+    pub id: PassId,
+    pub name: String,
+    pub uses: Vec<(ResourceId, Usage)>,
+    pub colors: Vec<CompiledAttachment>,
+    pub depth_stencil: Option<CompiledAttachment>,
+    // recorded before the pass begins
+    pub barriers: Vec<CompiledBarrier>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PhysicalImage {
+    pub format: format::Format,
+    pub usage: image::Usage,
+}
+
+#[derive(Clone, Debug)]
+pub struct PhysicalBuffer {
+    pub size: u64,
+    pub usage: buffer::Usage,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompiledGraph {
+    pub resources: Vec<CompiledResource>,
+    // in execution order, without the culled passes
+    pub passes: Vec<CompiledPass>,
+    pub culled: Vec<(PassId, String)>,
+    pub images: Vec<PhysicalImage>,
+    pub buffers: Vec<PhysicalBuffer>,
+    // recorded after the last pass, moving the backbuffer to the present layout
+    pub final_barriers: Vec<CompiledBarrier>,
+}
+
+impl RenderGraphBuilder {
+    pub fn new() -> RenderGraphBuilder {
+        RenderGraphBuilder::default()
+    }
+
+    pub fn image(&mut self, name: &str, format: format::Format) -> ResourceId {
+        self.resource(name, ResourceDesc::Image { format })
+    }
+
+    pub fn buffer(&mut self, name: &str, size: u64) -> ResourceId {
+        self.resource(name, ResourceDesc::Buffer { size })
+    }
+
+    pub fn backbuffer(&mut self, name: &str, format: format::Format) -> ResourceId {
+        self.resource(
+            name,
+            ResourceDesc::Backbuffer {
+                format,
+                presented: true,
+            },
+        )
+    }
+
+    // a backbuffer for rendering without a swapchain, left ready to be copied from
+    pub fn offscreen_backbuffer(&mut self, name: &str, format: format::Format) -> ResourceId {
+        self.resource(
+            name,
+            ResourceDesc::Backbuffer {
+                format,
+                presented: false,
+            },
+        )
+    }
+
+    fn resource(&mut self, name: &str, desc: ResourceDesc) -> ResourceId {
+        self.resources.push((name.to_string(), desc));
+        self.resources.len() - 1
+    }
+
+    pub fn pass(&mut self, pass: PassBuilder) -> PassId {
+        self.passes.push(pass);
+        self.passes.len() - 1
+    }
+
+    fn validate(&self) -> Result<(), RenderGraphError> {
+        for pass in self.passes.iter() {
+            for (index, &(resource, usage)) in pass.uses.iter().enumerate() {
+                let (ref name, desc) = *self.resources.get(resource).ok_or_else(|| {
+                    RenderGraphError::UnknownResource {
+                        pass: pass.name.clone(),
+                        resource,
+                    }
+                })?;
+
+                let is_buffer = match desc {
+                    ResourceDesc::Buffer { .. } => true,
+                    _ => false,
+                };
+                let backbuffer_sampled = match (desc, usage) {
+                    (ResourceDesc::Backbuffer { .. }, Usage::Sampled) => true,
+                    _ => false,
+                };
+                let internal = usage == Usage::Present || usage == Usage::CopySource;
+                if is_buffer != usage.is_buffer() || internal || backbuffer_sampled {
+                    return Err(RenderGraphError::WrongResourceType {
+                        pass: pass.name.clone(),
+                        resource: name.clone(),
+                    });
+                }
+
+                if pass.uses[..index]
+                    .iter()
+                    .any(|&(other, _)| other == resource)
+                {
+                    return Err(RenderGraphError::DuplicateUse {
+                        pass: pass.name.clone(),
+                        resource: name.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // passes that have to run before each pass: readers wait for the last writer declared before
+    // them (or every writer if there is none), writers for the uses declared before them
+    fn dependencies(&self) -> Result<Vec<Vec<PassId>>, RenderGraphError> {
+        let mut dependencies = vec![Vec::new(); self.passes.len()];
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            for &(resource, usage) in pass.uses.iter() {
+                let writers: Vec<PassId> = self
+                    .passes
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, other)| {
+                        other.uses.iter().any(|&(other_resource, other_usage)| {
+                            other_resource == resource && other_usage.writes()
+                        })
+                    })
+                    .map(|(other, _)| other)
+                    .collect();
+
+                if usage.writes() {
+                    // readers that don't read anything written before them wait for us instead
+                    for (other, other_pass) in self.passes[..index].iter().enumerate() {
+                        let other_usage = other_pass
+                            .uses
+                            .iter()
+                            .find(|&&(other_resource, _)| other_resource == resource);
+                        if let Some(&(_, other_usage)) = other_usage {
+                            if other_usage.writes() || writers.iter().any(|&writer| writer < other)
+                            {
+                                dependencies[index].push(other);
+                            }
+                        }
+                    }
+                } else if writers.is_empty() {
+                    return Err(RenderGraphError::NeverWritten {
+                        pass: pass.name.clone(),
+                        resource: self.resources[resource].0.clone(),
+                    });
+                } else {
+                    match writers.iter().filter(|&&writer| writer < index).last() {
+                        Some(&writer) => dependencies[index].push(writer),
+                        None => dependencies[index].extend(writers),
+                    }
+                }
+            }
+
+            dependencies[index].sort();
+            dependencies[index].dedup();
+        }
+
+        Ok(dependencies)
+    }
+
+    pub fn compile(&self) -> Result<CompiledGraph, RenderGraphError> {
+        self.validate()?;
+        let dependencies = self.dependencies()?;
+
+        // keep only the passes the backbuffer depends on
+        let mut alive = vec![false; self.passes.len()];
+        let mut stack: Vec<PassId> = self
+            .passes
+            .iter()
+            .enumerate()
+            .filter(|&(_, pass)| {
+                pass.uses.iter().any(|&(resource, usage)| {
+                    usage.writes()
+                        && match self.resources[resource].1 {
+                            ResourceDesc::Backbuffer { .. } => true,
+                            _ => false,
+                        }
+                })
+            })
+            .map(|(index, _)| index)
+            .collect();
+        if stack.is_empty() {
+            return Err(RenderGraphError::NothingPresented);
+        }
+        while let Some(index) = stack.pop() {
+            if !alive[index] {
+                alive[index] = true;
+                stack.extend(dependencies[index].iter().cloned());
+            }
+        }
+
+        // topological order, ties broken by declaration order
+        let mut order: Vec<PassId> = Vec::new();
+        let mut scheduled = vec![false; self.passes.len()];
+        let alive_count = alive.iter().filter(|&&alive| alive).count();
+        while order.len() < alive_count {
+            let next = (0..self.passes.len()).find(|&index| {
+                alive[index]
+                    && !scheduled[index]
+                    && dependencies[index]
+                        .iter()
+                        .all(|&dependency| scheduled[dependency])
+            });
+            match next {
+                Some(index) => {
+                    scheduled[index] = true;
+                    order.push(index);
+                }
+                None => return Err(RenderGraphError::Cycle),
+            }
+        }
+
+        // uses of each resource in execution order, as (position, usage)
+        let mut resource_uses: Vec<Vec<(usize, Usage)>> = vec![Vec::new(); self.resources.len()];
+        for (position, &index) in order.iter().enumerate() {
+            for &(resource, usage) in self.passes[index].uses.iter() {
+                resource_uses[resource].push((position, usage));
+            }
+        }
+
+        let (resources, images, buffers) = self.assign_physical(&resource_uses);
+
+        let passes = order
+            .iter()
+            .enumerate()
+            .map(|(position, &index)| self.compile_pass(index, position, &resource_uses))
+            .collect();
+
+        let final_barriers = self
+            .resources
+            .iter()
+            .enumerate()
+            .filter_map(|(resource, &(_, desc))| {
+                let to = desc.final_usage()?;
+                resource_uses[resource]
+                    .last()
+                    .map(|&(_, last)| CompiledBarrier {
+                        resource,
+                        from: last,
+                        to,
+                        discard: false,
+                    })
+            })
+            .collect();
+
+        Ok(CompiledGraph {
+            resources,
+            passes,
+            culled: (0..self.passes.len())
+                .filter(|&index| !alive[index])
+                .map(|index| (index, self.passes[index].name.clone()))
+                .collect(),
+            images,
+            buffers,
+            final_barriers,
+        })
+    }
+
+    // transient images with the same description share memory when their lifetimes don't
+    // overlap; buffers are small and not aliased, so barriers on them never cross resources
+    fn assign_physical(
+        &self,
+        resource_uses: &[Vec<(usize, Usage)>],
+    ) -> (
+        Vec<CompiledResource>,
+        Vec<PhysicalImage>,
+        Vec<PhysicalBuffer>,
+    ) {
+        let mut images: Vec<PhysicalImage> = Vec::new();
+        // last position each physical image is used at
+        let mut image_ends: Vec<usize> = Vec::new();
+        let mut buffers: Vec<PhysicalBuffer> = Vec::new();
+
+        let mut by_first_use: Vec<ResourceId> = (0..self.resources.len())
+            .filter(|&resource| !resource_uses[resource].is_empty())
+            .collect();
+        by_first_use.sort_by_key(|&resource| resource_uses[resource][0].0);
+
+        let mut physical = vec![None; self.resources.len()];
+        for resource in by_first_use {
+            let uses = &resource_uses[resource];
+            match self.resources[resource].1 {
+                ResourceDesc::Image { format } => {
+                    let mut usage = image::Usage::empty();
+                    for &(_, resource_usage) in uses.iter() {
+                        usage |= match resource_usage {
+                            Usage::Color { .. } => image::Usage::COLOR_ATTACHMENT,
+                            Usage::DepthStencil { .. } => image::Usage::DEPTH_STENCIL_ATTACHMENT,
+                            _ => image::Usage::SAMPLED,
+                        };
+                    }
+
+                    let (start, end) = (uses[0].0, uses[uses.len() - 1].0);
+                    let reusable = images.iter().enumerate().position(|(index, image)| {
+                        image.format == format && image.usage == usage && image_ends[index] < start
+                    });
+                    physical[resource] = Some(match reusable {
+                        Some(index) => {
+                            image_ends[index] = end;
+                            index
+                        }
+                        None => {
+                            images.push(PhysicalImage { format, usage });
+                            image_ends.push(end);
+                            images.len() - 1
+                        }
+                    });
+                }
+                ResourceDesc::Buffer { size } => {
+                    buffers.push(PhysicalBuffer {
+                        size,
+                        usage: buffer::Usage::STORAGE,
+                    });
+                    physical[resource] = Some(buffers.len() - 1);
+                }
+                ResourceDesc::Backbuffer { .. } => {}
+            }
+        }
+
+        let resources = self
+            .resources
+            .iter()
+            .zip(physical)
+            .map(|(&(ref name, desc), physical)| CompiledResource {
+                name: name.clone(),
+                desc,
+                physical,
+            })
+            .collect();
+
+        (resources, images, buffers)
+    }
+
+    fn compile_pass(
+        &self,
+        index: PassId,
+        position: usize,
+        resource_uses: &[Vec<(usize, Usage)>],
+    ) -> CompiledPass {
+        let pass = &self.passes[index];
+        let mut colors = Vec::new();
+        let mut depth_stencil = None;
+        let mut barriers = Vec::new();
+
+        for &(resource, usage) in pass.uses.iter() {
+            let uses = &resource_uses[resource];
+            let current = uses
+                .iter()
+                .position(|&(use_position, _)| use_position == position)
+                .unwrap();
+            let final_usage = self.resources[resource].1.final_usage();
+            let is_backbuffer = final_usage.is_some();
+
+            let from = if current > 0 {
+                uses[current - 1].1
+            } else if let Some(final_usage) = final_usage {
+                final_usage
+            } else {
+                uses[uses.len() - 1].1
+            };
+            barriers.push(CompiledBarrier {
+                resource,
+                from,
+                to: usage,
+                discard: current == 0 || usage.clears(),
+            });
+
+            let load = if usage.clears() {
+                pass::AttachmentLoadOp::Clear
+            } else if current > 0 {
+                pass::AttachmentLoadOp::Load
+            } else {
+                pass::AttachmentLoadOp::DontCare
+            };
+            let store = if current + 1 < uses.len() || is_backbuffer {
+                pass::AttachmentStoreOp::Store
+            } else {
+                pass::AttachmentStoreOp::DontCare
+            };
+            let attachment = CompiledAttachment {
+                resource,
+                ops: pass::AttachmentOps { load, store },
+                layout: usage.layout(),
+            };
+
+            match usage {
+                Usage::Color { .. } => colors.push(attachment),
+                Usage::DepthStencil { .. } => depth_stencil = Some(attachment),
+                _ => {}
+            }
+        }
+
+        CompiledPass {
+            id: index,
+            name: pass.name.clone(),
+            uses: pass.uses.clone(),
+            colors,
+            depth_stencil,
+            barriers,
+        }
+    }
+}
+
+// labels are quoted strings in the dot language
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl CompiledGraph {
+    // passes in execution order with the barriers recorded before them, resources with the
+    // transient image or buffer backing them, and an edge for every use labelled with its load and
+    // store ops and layout; culled passes are drawn dashed and unconnected
+    pub fn write_dot(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "digraph render_graph {{")?;
+        writeln!(writer, "    rankdir=LR;")?;
+        writeln!(writer, "    node [fontname=\"monospace\"];")?;
+        writeln!(writer, "    edge [fontname=\"monospace\", fontsize=10];")?;
+
+        for (id, resource) in self.resources.iter().enumerate() {
+            let backing = match (&resource.desc, resource.physical) {
+                (&ResourceDesc::Image { format }, Some(physical)) => {
+                    format!("{:?}\\nimage {}", format, physical)
+                }
+                (&ResourceDesc::Buffer { size }, Some(physical)) => {
+                    format!("{} bytes\\nbuffer {}", size, physical)
+                }
+                (
+                    &ResourceDesc::Backbuffer {
+                        format,
+                        presented: true,
+                    },
+                    _,
+                ) => format!("{:?}\\nswapchain", format),
+                (
+                    &ResourceDesc::Backbuffer {
+                        format,
+                        presented: false,
+                    },
+                    _,
+                ) => format!("{:?}\\noffscreen", format),
+                // declared but not used by any pass that survived culling
+                (_, None) => "unused".to_string(),
+            };
+            writeln!(
+                writer,
+                "    r{} [shape=ellipse, label=\"{}\\n{}\"];",
+                id,
+                escape_dot(&resource.name),
+                backing
+            )?;
+        }
+
+        for (order, pass) in self.passes.iter().enumerate() {
+            let mut label = format!("{}: {}\\l", order, escape_dot(&pass.name));
+            for barrier in pass.barriers.iter() {
+                label.push_str(&self.describe_barrier(barrier));
+            }
+            writeln!(
+                writer,
+                "    p{} [shape=box, style=bold, label=\"{}\"];",
+                pass.id, label
+            )?;
+
+            for &(resource, usage) in pass.uses.iter() {
+                let attachment = pass
+                    .colors
+                    .iter()
+                    .chain(pass.depth_stencil.iter())
+                    .find(|attachment| attachment.resource == resource);
+                let label = match attachment {
+                    Some(attachment) => format!(
+                        "{:?}\\nload {:?}, store {:?}\\n{:?}",
+                        usage, attachment.ops.load, attachment.ops.store, attachment.layout
+                    ),
+                    None if usage.is_buffer() => format!("{:?}", usage),
+                    None => format!("{:?}\\n{:?}", usage, usage.layout()),
+                };
+                if usage.writes() {
+                    writeln!(
+                        writer,
+                        "    p{} -> r{} [label=\"{}\"];",
+                        pass.id, resource, label
+                    )?;
+                } else {
+                    writeln!(
+                        writer,
+                        "    r{} -> p{} [label=\"{}\"];",
+                        resource, pass.id, label
+                    )?;
+                }
+            }
+        }
+
+        if !self.final_barriers.is_empty() {
+            let mut label = "present\\l".to_string();
+            for barrier in self.final_barriers.iter() {
+                label.push_str(&self.describe_barrier(barrier));
+            }
+            writeln!(writer, "    present [shape=box, label=\"{}\"];", label)?;
+            for barrier in self.final_barriers.iter() {
+                writeln!(writer, "    r{} -> present;", barrier.resource)?;
+            }
+        }
+
+        for &(id, ref name) in self.culled.iter() {
+            writeln!(
+                writer,
+                "    p{} [shape=box, style=dashed, label=\"{} (culled)\"];",
+                id,
+                escape_dot(name)
+            )?;
+        }
+
+        writeln!(writer, "}}")?;
+        writer.flush()
+    }
+
+    // one left aligned line of a node label
+    fn describe_barrier(&self, barrier: &CompiledBarrier) -> String {
+        let name = escape_dot(&self.resources[barrier.resource].name);
+        if barrier.to.is_buffer() {
+            format!(
+                "barrier {}: {:?} -> {:?}\\l",
+                name, barrier.from, barrier.to
+            )
+        } else {
+            let from = if barrier.discard {
+                image::Layout::Undefined
+            } else {
+                barrier.from.layout()
+            };
+            format!(
+                "barrier {}: {:?} -> {:?}\\l",
+                name,
+                from,
+                barrier.to.layout()
+            )
+        }
+    }
+
+    // how the backbuffer is left after the last pass
+    pub fn backbuffer_usage(&self) -> Usage {
+        self.resources
+            .iter()
+            .filter_map(|resource| resource.desc.final_usage())
+            .next()
+            .unwrap_or(Usage::Present)
+    }
+
+    fn format(&self, resource: ResourceId) -> format::Format {
+        match self.resources[resource].desc {
+            ResourceDesc::Image { format } | ResourceDesc::Backbuffer { format, .. } => format,
+            ResourceDesc::Buffer { .. } => unreachable!(),
+        }
+    }
+
+    fn uses_backbuffer(&self, pass: &CompiledPass) -> bool {
+        pass.uses
+            .iter()
+            .any(|&(resource, _)| self.resources[resource].physical.is_none())
+    }
+}
+
+struct TransientImage {
+    image: resource::Image,
+    view: resource::ImageView,
+    allocation: Allocation,
+    id: ImageId,
+}
+
+struct TransientBuffer {
+    buffer: resource::Buffer,
+    allocation: Allocation,
+}
+
+// the device objects backing the graph's transient resources
+#[derive(Default)]
+pub struct GraphResources {
+    physical: Vec<Option<usize>>,
+    images: Vec<TransientImage>,
+    buffers: Vec<TransientBuffer>,
+}
+
+impl GraphResources {
+    pub fn image_view(&self, resource: ResourceId) -> &<back::Backend as Backend>::ImageView {
+        &self.images[self.physical[resource].expect("the backbuffer isn't a transient image")].view
+    }
+
+    pub fn buffer(&self, resource: ResourceId) -> &<back::Backend as Backend>::Buffer {
+        &self.buffers[self.physical[resource].expect("the backbuffer isn't a transient buffer")]
+            .buffer
+    }
+}
+
+struct PhysicalPass {
+    render_pass: resource::RenderPass,
+    // one per swapchain image if the pass renders to the backbuffer
+    framebuffers: Vec<resource::Framebuffer>,
+}
+
+pub struct RenderGraph {
+    compiled: CompiledGraph,
+    passes: Vec<PhysicalPass>,
+    resources: GraphResources,
+    tracker: ImageTracker,
+    // tracker ids of the swapchain images
+    backbuffer_ids: Vec<ImageId>,
+    clear_values: Vec<command::ClearValue>,
+    extent: window::Extent2D,
+}
+
+impl RenderGraph {
+    // creates the render passes; `resize` has to be called before the first frame is recorded
+    pub unsafe fn new(
+        device: &DeviceRef,
+        compiled: CompiledGraph,
+    ) -> Result<RenderGraph, RenderPassError> {
+        let mut passes = Vec::new();
+        for pass in compiled.passes.iter() {
+            let mut builder = RenderPassBuilder::new();
+            let mut subpass = SubpassBuilder::new();
+            for (attachment_id, attachment) in pass
+                .colors
+                .iter()
+                .chain(pass.depth_stencil.iter())
+                .enumerate()
+            {
+                let format = compiled.format(attachment.resource);
+                let stencil_ops = if format
+                    .surface_desc()
+                    .aspects
+                    .contains(format::Aspects::STENCIL)
+                {
+                    attachment.ops
+                } else {
+                    pass::AttachmentOps::DONT_CARE
+                };
+                // layouts outside the pass are handled by the barriers between passes
+                builder = builder.attachment(pass::Attachment {
+                    format: Some(format),
+                    samples: 1,
+                    ops: attachment.ops,
+                    stencil_ops,
+                    layouts: attachment.layout..attachment.layout,
+                });
+                subpass = if attachment_id < pass.colors.len() {
+                    subpass.color(attachment_id)
+                } else {
+                    subpass.depth_stencil(attachment_id)
+                };
+            }
+
+            // hal assumes pipeline bind point is GRAPHICS
+            passes.push(PhysicalPass {
+                render_pass: builder.subpass(subpass).build(device)?,
+                framebuffers: Vec::new(),
+            });
+        }
+
+        let clear_values = compiled
+            .resources
+            .iter()
+            .map(|resource| match resource.desc {
+                ResourceDesc::Image { format } | ResourceDesc::Backbuffer { format, .. }
+                    if format
+                        .surface_desc()
+                        .aspects
+                        .contains(format::Aspects::DEPTH) =>
+                {
+                    command::ClearValue::DepthStencil(command::ClearDepthStencil(1.0, 0))
+                }
+                _ => command::ClearValue::Color(command::ClearColor::Float([0.0, 0.0, 0.0, 1.0])),
+            })
+            .collect();
+
+        Ok(RenderGraph {
+            compiled,
+            passes,
+            resources: GraphResources::default(),
+            tracker: ImageTracker::new(),
+            backbuffer_ids: Vec::new(),
+            clear_values,
+            extent: window::Extent2D {
+                width: 0,
+                height: 0,
+            },
+        })
+    }
+
+    pub fn compiled(&self) -> &CompiledGraph {
+        &self.compiled
+    }
+
+    // None if the pass was culled
+    pub fn render_pass(&self, pass: PassId) -> Option<&<back::Backend as Backend>::RenderPass> {
+        self.compiled
+            .passes
+            .iter()
+            .position(|compiled| compiled.id == pass)
+            .map(|position| &*self.passes[position].render_pass)
+    }
+
+    pub fn resources(&self) -> &GraphResources {
+        &self.resources
+    }
+
+    // for commands recorded on a backbuffer after the graph, which have to leave it the way the
+    // graph did, see `CompiledGraph::backbuffer_usage`
+    pub fn backbuffer_tracker(&mut self, image_index: usize) -> (&mut ImageTracker, ImageId) {
+        (&mut self.tracker, self.backbuffer_ids[image_index])
+    }
+
+    pub fn set_clear_value(&mut self, resource: ResourceId, clear_value: command::ClearValue) {
+        self.clear_values[resource] = clear_value;
+    }
+
+    // destroys everything that depends on the swapchain, the device must be idle
+    pub fn release(&mut self, memory_allocator: &mut MemoryAllocator) {
+        for pass in self.passes.iter_mut() {
+            pass.framebuffers.clear();
+        }
+        for transient in self.resources.images.drain(..) {
+            self.tracker.unregister(transient.id);
+            memory_allocator.free(transient.allocation);
+        }
+        for transient in self.resources.buffers.drain(..) {
+            memory_allocator.free(transient.allocation);
+        }
+        for id in self.backbuffer_ids.drain(..) {
+            self.tracker.unregister(id);
+        }
+    }
+
+    // (re)creates the transient resources and framebuffers for a new swapchain, the device must
+    // be idle; only the views of the backbuffers are used, their images may be owned or not
+    pub unsafe fn resize<I>(
+        &mut self,
+        device: &DeviceRef,
+        memory_allocator: &mut MemoryAllocator,
+        extent: window::Extent2D,
+        backbuffers: &[(I, resource::ImageView)],
+    ) {
+        self.release(memory_allocator);
+
+        self.extent = extent;
+        self.resources.physical = self
+            .compiled
+            .resources
+            .iter()
+            .map(|resource| resource.physical)
+            .collect();
+
+        for physical in self.compiled.images.iter() {
+            let (image, view, allocation) =
+                RenderGraph::create_image(device, memory_allocator, physical, extent);
+            let id = self.tracker.register(
+                1,
+                1,
+                ImageState::new(
+                    pso::PipelineStage::empty(),
+                    image::Access::empty(),
+                    image::Layout::Undefined,
+                ),
+            );
+            self.resources.images.push(TransientImage {
+                image,
+                view,
+                allocation,
+                id,
+            });
+        }
+
+        for physical in self.compiled.buffers.iter() {
+            let mut buffer = resource::buffer(
+                device,
+                device
+                    .create_buffer(physical.size, physical.usage)
+                    .expect("failed to create transient buffer!"),
+            );
+            let requirements = device.get_buffer_requirements(&buffer);
+            let allocation = memory_allocator
+                .allocate(
+                    requirements,
+                    Properties::DEVICE_LOCAL,
+                    Strategy::FreeList,
+                    ResourceKind::Linear,
+                )
+                .expect("failed to allocate transient buffer memory!");
+            device
+                .bind_buffer_memory(allocation.memory(), allocation.offset(), &mut buffer)
+                .expect("failed to bind transient buffer memory!");
+            self.resources
+                .buffers
+                .push(TransientBuffer { buffer, allocation });
+        }
+
+        let acquired = self.compiled.backbuffer_usage().image_state();
+        self.backbuffer_ids = backbuffers
+            .iter()
+            .map(|_| {
+                self.tracker.register(
+                    1,
+                    1,
+                    ImageState {
+                        layout: image::Layout::Undefined,
+                        ..acquired
+                    },
+                )
+            })
+            .collect();
+
+        for (pass, physical_pass) in self.compiled.passes.iter().zip(self.passes.iter_mut()) {
+            let framebuffer_count = if self.compiled.uses_backbuffer(pass) {
+                backbuffers.len()
+            } else {
+                1
+            };
+
+            for backbuffer in backbuffers[..framebuffer_count].iter() {
+                let attachments: Vec<&<back::Backend as Backend>::ImageView> = pass
+                    .colors
+                    .iter()
+                    .chain(pass.depth_stencil.iter())
+                    .map(
+                        |attachment| match self.resources.physical[attachment.resource] {
+                            Some(physical) => &*self.resources.images[physical].view,
+                            None => &*backbuffer.1,
+                        },
+                    )
+                    .collect();
+                let framebuffer = device
+                    .create_framebuffer(
+                        &physical_pass.render_pass,
+                        attachments,
+                        image::Extent {
+                            width: extent.width as _,
+                            height: extent.height as _,
+                            depth: 1,
+                        },
+                    )
+                    .expect("failed to create framebuffer!");
+                physical_pass
+                    .framebuffers
+                    .push(resource::framebuffer(device, framebuffer));
+            }
+        }
+    }
+
+    unsafe fn create_image(
+        device: &DeviceRef,
+        memory_allocator: &mut MemoryAllocator,
+        physical: &PhysicalImage,
+        extent: window::Extent2D,
+    ) -> (resource::Image, resource::ImageView, Allocation) {
+        let mut transient_image = resource::image(
+            device,
+            device
+                .create_image(
+                    image::Kind::D2(extent.width, extent.height, 1, 1),
+                    1,
+                    physical.format,
+                    image::Tiling::Optimal,
+                    physical.usage,
+                    image::ViewCapabilities::empty(),
+                )
+                .expect("failed to create transient image!"),
+        );
+        let requirements = device.get_image_requirements(&transient_image);
+        let allocation = memory_allocator
+            .allocate(
+                requirements,
+                Properties::DEVICE_LOCAL,
+                Strategy::FreeList,
+                ResourceKind::Optimal,
+            )
+            .expect("failed to allocate transient image memory!");
+        device
+            .bind_image_memory(
+                allocation.memory(),
+                allocation.offset(),
+                &mut transient_image,
+            )
+            .expect("failed to bind transient image memory!");
+
+        let view = resource::image_view(
+            device,
+            device
+                .create_image_view(
+                    &transient_image,
+                    image::ViewKind::D2,
+                    physical.format,
+                    format::Swizzle::NO,
+                    image::SubresourceRange {
+                        aspects: physical.format.surface_desc().aspects,
+                        levels: 0..1,
+                        layers: 0..1,
+                    },
+                )
+                .expect("failed to create transient image view!"),
+        );
+
+        (transient_image, view, allocation)
+    }
+
+    // `record_pass` is called inside each pass with the id it was declared with
+    pub unsafe fn record<F>(
+        &mut self,
+        command_buffer: &mut FrameCommandBuffer,
+        image_index: usize,
+        backbuffer: &<back::Backend as Backend>::Image,
+        mut record_pass: F,
+    ) where
+        F: FnMut(PassId, &mut command::RenderPassInlineEncoder<back::Backend>, &GraphResources),
+    {
+        let render_area = pso::Rect {
+            x: 0,
+            y: 0,
+            w: self.extent.width as _,
+            h: self.extent.height as _,
+        };
+
+        for (pass, physical_pass) in self.compiled.passes.iter().zip(self.passes.iter()) {
+            RenderGraph::record_barriers(
+                &self.compiled,
+                &self.resources,
+                &mut self.tracker,
+                &pass.barriers,
+                command_buffer,
+                (self.backbuffer_ids[image_index], backbuffer),
+            );
+
+            let framebuffer = if physical_pass.framebuffers.len() > 1 {
+                &physical_pass.framebuffers[image_index]
+            } else {
+                &physical_pass.framebuffers[0]
+            };
+            let clear_values: Vec<command::ClearValue> = pass
+                .colors
+                .iter()
+                .chain(pass.depth_stencil.iter())
+                .map(|attachment| self.clear_values[attachment.resource])
+                .collect();
+
+            let mut encoder = command_buffer.begin_render_pass_inline(
+                &physical_pass.render_pass,
+                framebuffer,
+                render_area,
+                clear_values.iter(),
+            );
+            record_pass(pass.id, &mut encoder, &self.resources);
+        }
+
+        RenderGraph::record_barriers(
+            &self.compiled,
+            &self.resources,
+            &mut self.tracker,
+            &self.compiled.final_barriers,
+            command_buffer,
+            (self.backbuffer_ids[image_index], backbuffer),
+        );
+    }
+
+    unsafe fn record_barriers(
+        compiled: &CompiledGraph,
+        resources: &GraphResources,
+        tracker: &mut ImageTracker,
+        compiled_barriers: &[CompiledBarrier],
+        command_buffer: &mut FrameCommandBuffer,
+        // the tracked id and image of the backbuffer being recorded to
+        backbuffer: (ImageId, &<back::Backend as Backend>::Image),
+    ) {
+        let mut barriers = Barriers::new();
+
+        for barrier in compiled_barriers.iter() {
+            let resource = &compiled.resources[barrier.resource];
+            if let ResourceDesc::Buffer { .. } = resource.desc {
+                barriers.buffer(
+                    &resources.buffers[resource.physical.unwrap()].buffer,
+                    barrier.from.buffer_state(),
+                    barrier.to.buffer_state(),
+                );
+                continue;
+            }
+
+            let (id, target) = match resource.physical {
+                Some(physical) => (
+                    resources.images[physical].id,
+                    &*resources.images[physical].image,
+                ),
+                None => backbuffer,
+            };
+            let range = image::SubresourceRange {
+                aspects: compiled.format(barrier.resource).surface_desc().aspects,
+                levels: 0..1,
+                layers: 0..1,
+            };
+            // the tracker knows the actual previous state, e.g. of an aliased image
+            if barrier.discard {
+                tracker.discard(id, &range);
+            }
+            tracker.require(&mut barriers, id, target, &range, barrier.to.image_state());
+        }
+
+        barriers.record(command_buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLOR: format::Format = format::Format::Rgba8Srgb;
+
+    fn pass_names(compiled: &CompiledGraph) -> Vec<&str> {
+        compiled
+            .passes
+            .iter()
+            .map(|pass| pass.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn passes_not_contributing_to_the_backbuffer_are_culled() {
+        let mut builder = RenderGraphBuilder::new();
+        let backbuffer = builder.backbuffer("backbuffer", COLOR);
+        let debug = builder.image("debug", COLOR);
+        builder.pass(PassBuilder::new("debug").clear_color(debug));
+        builder.pass(PassBuilder::new("main").clear_color(backbuffer));
+
+        let compiled = builder.compile().unwrap();
+        assert_eq!(pass_names(&compiled), ["main"]);
+        assert_eq!(compiled.culled, vec![(0, "debug".to_string())]);
+        // nothing uses the culled pass's image, so it isn't created
+        assert_eq!(compiled.resources[debug].physical, None);
+        assert!(compiled.images.is_empty());
+    }
+
+    #[test]
+    fn readers_run_after_the_pass_writing_what_they_read() {
+        let mut builder = RenderGraphBuilder::new();
+        let backbuffer = builder.backbuffer("backbuffer", COLOR);
+        let shadow = builder.image("shadow", format::Format::D32Float);
+        // declared before the pass it depends on
+        builder.pass(
+            PassBuilder::new("main")
+                .sampled(shadow)
+                .clear_color(backbuffer),
+        );
+        builder.pass(PassBuilder::new("shadow").clear_depth_stencil(shadow));
+
+        let compiled = builder.compile().unwrap();
+        assert_eq!(pass_names(&compiled), ["shadow", "main"]);
+        assert!(compiled.culled.is_empty());
+        // the shadow map is read afterwards, so it has to be stored
+        let depth = compiled.passes[0].depth_stencil.as_ref().unwrap();
+        assert_eq!(depth.ops.load, pass::AttachmentLoadOp::Clear);
+        assert_eq!(depth.ops.store, pass::AttachmentStoreOp::Store);
+        assert_eq!(compiled.final_barriers.len(), 1);
+        assert_eq!(compiled.final_barriers[0].to, Usage::Present);
+    }
+
+    #[test]
+    fn images_with_disjoint_lifetimes_share_memory() {
+        let mut builder = RenderGraphBuilder::new();
+        let backbuffer = builder.backbuffer("backbuffer", COLOR);
+        let first = builder.image("first", COLOR);
+        let second = builder.image("second", COLOR);
+        let third = builder.image("third", COLOR);
+        builder.pass(PassBuilder::new("first").clear_color(first));
+        builder.pass(
+            PassBuilder::new("second")
+                .sampled(first)
+                .clear_color(second),
+        );
+        builder.pass(PassBuilder::new("third").sampled(second).clear_color(third));
+        builder.pass(
+            PassBuilder::new("main")
+                .sampled(third)
+                .clear_color(backbuffer),
+        );
+
+        let compiled = builder.compile().unwrap();
+        assert_eq!(pass_names(&compiled), ["first", "second", "third", "main"]);
+        assert_eq!(compiled.images.len(), 2);
+        assert_eq!(
+            compiled.resources[first].physical,
+            compiled.resources[third].physical
+        );
+        assert_ne!(
+            compiled.resources[first].physical,
+            compiled.resources[second].physical
+        );
+    }
+
+    #[test]
+    fn invalid_graphs_are_rejected() {
+        let mut builder = RenderGraphBuilder::new();
+        let image = builder.image("image", COLOR);
+        builder.pass(PassBuilder::new("offscreen").clear_color(image));
+        assert_eq!(
+            builder.compile().unwrap_err(),
+            RenderGraphError::NothingPresented
+        );
+
+        let mut builder = RenderGraphBuilder::new();
+        let backbuffer = builder.backbuffer("backbuffer", COLOR);
+        let image = builder.image("image", COLOR);
+        builder.pass(
+            PassBuilder::new("main")
+                .sampled(image)
+                .clear_color(backbuffer),
+        );
+        assert_eq!(
+            builder.compile().unwrap_err(),
+            RenderGraphError::NeverWritten {
+                pass: "main".to_string(),
+                resource: "image".to_string(),
+            }
+        );
+
+        let mut builder = RenderGraphBuilder::new();
+        let backbuffer = builder.backbuffer("backbuffer", COLOR);
+        builder.pass(
+            PassBuilder::new("main")
+                .clear_color(backbuffer)
+                .sampled(backbuffer),
+        );
+        assert_eq!(
+            builder.compile().unwrap_err(),
+            RenderGraphError::WrongResourceType {
+                pass: "main".to_string(),
+                resource: "backbuffer".to_string(),
+            }
+        );
+
+        let mut builder = RenderGraphBuilder::new();
+        let backbuffer = builder.backbuffer("backbuffer", COLOR);
+        builder.pass(
+            PassBuilder::new("main")
+                .clear_color(backbuffer)
+                .color(backbuffer),
+        );
+        assert_eq!(
+            builder.compile().unwrap_err(),
+            RenderGraphError::DuplicateUse {
+                pass: "main".to_string(),
+                resource: "backbuffer".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn passes_depending_on_each_other_are_rejected() {
+        let mut builder = RenderGraphBuilder::new();
+        let backbuffer = builder.backbuffer("backbuffer", COLOR);
+        let a = builder.image("a", COLOR);
+        let b = builder.image("b", COLOR);
+        builder.pass(PassBuilder::new("a").sampled(b).color(a));
+        builder.pass(PassBuilder::new("b").sampled(a).color(b));
+        builder.pass(PassBuilder::new("main").sampled(a).clear_color(backbuffer));
+        assert_eq!(builder.compile().unwrap_err(), RenderGraphError::Cycle);
+    }
+}
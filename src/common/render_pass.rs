@@ -0,0 +1,534 @@
+use common::resource::{self, DeviceRef};
+use hal::{format, image, pass, Device};
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderPassError {
+    NoSubpasses,
+    // attachment reference in subpass points past the end of the attachment list
+    InvalidAttachment {
+        subpass: usize,
+        attachment: pass::AttachmentId,
+    },
+    // same attachment referenced twice as an output of the same subpass
+    DuplicateAttachment {
+        subpass: usize,
+        attachment: pass::AttachmentId,
+    },
+    // attachment used as color but not a color format, or as depth/stencil without depth or stencil
+    WrongAspect {
+        subpass: usize,
+        attachment: pass::AttachmentId,
+    },
+    // resolves must be empty or match the color attachments one to one
+    ResolveCountMismatch {
+        subpass: usize,
+        colors: usize,
+        resolves: usize,
+    },
+    // resolve targets must be single sampled and resolve sources multisampled
+    InvalidResolve {
+        subpass: usize,
+        attachment: pass::AttachmentId,
+    },
+    // preserved attachment is also used by the subpass
+    PreservedAttachmentUsed {
+        subpass: usize,
+        attachment: pass::AttachmentId,
+    },
+    InvalidDependency {
+        dependency: usize,
+    },
+    OutOfMemory,
+}
+
+impl fmt::Display for RenderPassError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenderPassError::NoSubpasses => write!(f, "render pass has no subpasses"),
+            RenderPassError::InvalidAttachment {
+                subpass,
+                attachment,
+            } => write!(
+                f,
+                "subpass {} references attachment {} which does not exist",
+                subpass, attachment
+            ),
+            RenderPassError::DuplicateAttachment {
+                subpass,
+                attachment,
+            } => write!(
+                f,
+                "subpass {} uses attachment {} more than once",
+                subpass, attachment
+            ),
+            RenderPassError::WrongAspect {
+                subpass,
+                attachment,
+            } => write!(
+                f,
+                "subpass {} uses attachment {} with a format of the wrong aspect",
+                subpass, attachment
+            ),
+            RenderPassError::ResolveCountMismatch {
+                subpass,
+                colors,
+                resolves,
+            } => write!(
+                f,
+                "subpass {} has {} resolve attachments for {} color attachments",
+                subpass, resolves, colors
+            ),
+            RenderPassError::InvalidResolve {
+                subpass,
+                attachment,
+            } => write!(
+                f,
+                "subpass {} resolves attachment {} with mismatched sample counts",
+                subpass, attachment
+            ),
+            RenderPassError::PreservedAttachmentUsed {
+                subpass,
+                attachment,
+            } => write!(
+                f,
+                "subpass {} preserves attachment {} which it also uses",
+                subpass, attachment
+            ),
+            RenderPassError::InvalidDependency { dependency } => write!(
+                f,
+                "subpass dependency {} refers to a missing subpass or goes backwards",
+                dependency
+            ),
+            RenderPassError::OutOfMemory => write!(f, "out of memory creating render pass"),
+        }
+    }
+}
+
+impl Error for RenderPassError {}
+
+#[derive(Clone, Debug, Default)]
+pub struct SubpassBuilder {
+    colors: Vec<pass::AttachmentRef>,
+    depth_stencil: Option<pass::AttachmentRef>,
+    inputs: Vec<pass::AttachmentRef>,
+    resolves: Vec<pass::AttachmentRef>,
+    preserves: Vec<pass::AttachmentId>,
+}
+
+impl SubpassBuilder {
+    pub fn new() -> SubpassBuilder {
+        SubpassBuilder::default()
+    }
+
+    pub fn color(mut self, attachment: pass::AttachmentId) -> Self {
+        self.colors
+            .push((attachment, image::Layout::ColorAttachmentOptimal));
+        self
+    }
+
+    pub fn depth_stencil(mut self, attachment: pass::AttachmentId) -> Self {
+        self.depth_stencil = Some((attachment, image::Layout::DepthStencilAttachmentOptimal));
+        self
+    }
+
+    pub fn input(mut self, attachment: pass::AttachmentId) -> Self {
+        self.inputs
+            .push((attachment, image::Layout::ShaderReadOnlyOptimal));
+        self
+    }
+
+    // resolves are matched to color attachments in the order both are added
+    pub fn resolve(mut self, attachment: pass::AttachmentId) -> Self {
+        self.resolves
+            .push((attachment, image::Layout::ColorAttachmentOptimal));
+        self
+    }
+
+    pub fn preserve(mut self, attachment: pass::AttachmentId) -> Self {
+        self.preserves.push(attachment);
+        self
+    }
+
+    fn desc(&self) -> pass::SubpassDesc {
+        pass::SubpassDesc {
+            colors: &self.colors,
+            depth_stencil: self.depth_stencil.as_ref(),
+            inputs: &self.inputs,
+            resolves: &self.resolves,
+            preserves: &self.preserves,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RenderPassBuilder {
+    attachments: Vec<pass::Attachment>,
+    subpasses: Vec<SubpassBuilder>,
+    dependencies: Vec<pass::SubpassDependency>,
+}
+
+impl RenderPassBuilder {
+    pub fn new() -> RenderPassBuilder {
+        RenderPassBuilder::default()
+    }
+
+    // attachments are referenced by subpasses through the order they are added in
+    pub fn attachment(mut self, attachment: pass::Attachment) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    pub fn subpass(mut self, subpass: SubpassBuilder) -> Self {
+        self.subpasses.push(subpass);
+        self
+    }
+
+    pub fn dependency(mut self, dependency: pass::SubpassDependency) -> Self {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    fn has_aspect(&self, attachment: pass::AttachmentId, aspects: format::Aspects) -> bool {
+        // attachments without a format can't be checked here; the backend decides
+        self.attachments[attachment].format.map_or(true, |format| {
+            format.surface_desc().aspects.intersects(aspects)
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), RenderPassError> {
+        if self.subpasses.is_empty() {
+            return Err(RenderPassError::NoSubpasses);
+        }
+
+        for (index, subpass) in self.subpasses.iter().enumerate() {
+            let check_exists = |attachment: pass::AttachmentId| {
+                if attachment < self.attachments.len() {
+                    Ok(())
+                } else {
+                    Err(RenderPassError::InvalidAttachment {
+                        subpass: index,
+                        attachment,
+                    })
+                }
+            };
+
+            let mut outputs: Vec<pass::AttachmentId> = Vec::new();
+            for &(attachment, _) in subpass
+                .colors
+                .iter()
+                .chain(subpass.depth_stencil.iter())
+                .chain(subpass.resolves.iter())
+            {
+                check_exists(attachment)?;
+                if outputs.contains(&attachment) {
+                    return Err(RenderPassError::DuplicateAttachment {
+                        subpass: index,
+                        attachment,
+                    });
+                }
+                outputs.push(attachment);
+            }
+
+            for &(attachment, _) in subpass.inputs.iter() {
+                check_exists(attachment)?;
+            }
+
+            for &(attachment, _) in subpass.colors.iter().chain(subpass.resolves.iter()) {
+                if !self.has_aspect(attachment, format::Aspects::COLOR) {
+                    return Err(RenderPassError::WrongAspect {
+                        subpass: index,
+                        attachment,
+                    });
+                }
+            }
+
+            if let Some((attachment, _)) = subpass.depth_stencil {
+                if !self.has_aspect(
+                    attachment,
+                    format::Aspects::DEPTH | format::Aspects::STENCIL,
+                ) {
+                    return Err(RenderPassError::WrongAspect {
+                        subpass: index,
+                        attachment,
+                    });
+                }
+            }
+
+            if !subpass.resolves.is_empty() {
+                if subpass.resolves.len() != subpass.colors.len() {
+                    return Err(RenderPassError::ResolveCountMismatch {
+                        subpass: index,
+                        colors: subpass.colors.len(),
+                        resolves: subpass.resolves.len(),
+                    });
+                }
+
+                for (&(color, _), &(resolve, _)) in
+                    subpass.colors.iter().zip(subpass.resolves.iter())
+                {
+                    if self.attachments[color].samples <= 1
+                        || self.attachments[resolve].samples != 1
+                    {
+                        return Err(RenderPassError::InvalidResolve {
+                            subpass: index,
+                            attachment: resolve,
+                        });
+                    }
+                }
+            }
+
+            for &attachment in subpass.preserves.iter() {
+                check_exists(attachment)?;
+                let used = outputs.contains(&attachment)
+                    || subpass.inputs.iter().any(|&(input, _)| input == attachment);
+                if used {
+                    return Err(RenderPassError::PreservedAttachmentUsed {
+                        subpass: index,
+                        attachment,
+                    });
+                }
+            }
+        }
+
+        for (index, dependency) in self.dependencies.iter().enumerate() {
+            let subpass_exists = |subpass_ref: &pass::SubpassRef| match *subpass_ref {
+                pass::SubpassRef::External => true,
+                pass::SubpassRef::Pass(subpass) => subpass < self.subpasses.len(),
+            };
+            let in_order = match (&dependency.passes.start, &dependency.passes.end) {
+                (&pass::SubpassRef::External, &pass::SubpassRef::External) => false,
+                (&pass::SubpassRef::Pass(src), &pass::SubpassRef::Pass(dst)) => src <= dst,
+                _ => true,
+            };
+
+            if !subpass_exists(&dependency.passes.start)
+                || !subpass_exists(&dependency.passes.end)
+                || !in_order
+            {
+                return Err(RenderPassError::InvalidDependency { dependency: index });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn build(
+        &self,
+        device: &DeviceRef,
+    ) -> Result<resource::RenderPass, RenderPassError> {
+        self.validate()?;
+
+        let subpasses: Vec<pass::SubpassDesc> = self
+            .subpasses
+            .iter()
+            .map(|subpass| subpass.desc())
+            .collect();
+
+        device
+            .create_render_pass(&self.attachments, &subpasses, &self.dependencies)
+            .map(|render_pass| resource::render_pass(device, render_pass))
+            .map_err(|_| RenderPassError::OutOfMemory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hal::pso;
+    use std::ops::Range;
+
+    const COLOR: pass::AttachmentId = 0;
+    const DEPTH: pass::AttachmentId = 1;
+    const MULTISAMPLED: pass::AttachmentId = 2;
+    const MULTISAMPLED_2: pass::AttachmentId = 3;
+
+    fn attachment(format: format::Format, samples: image::NumSamples) -> pass::Attachment {
+        pass::Attachment {
+            format: Some(format),
+            samples,
+            ops: pass::AttachmentOps::DONT_CARE,
+            stencil_ops: pass::AttachmentOps::DONT_CARE,
+            layouts: image::Layout::Undefined..image::Layout::General,
+        }
+    }
+
+    fn attachments() -> RenderPassBuilder {
+        RenderPassBuilder::new()
+            .attachment(attachment(format::Format::Rgba8Srgb, 1))
+            .attachment(attachment(format::Format::D32Float, 1))
+            .attachment(attachment(format::Format::Rgba8Srgb, 4))
+            .attachment(attachment(format::Format::Rgba8Srgb, 4))
+    }
+
+    fn dependency(passes: Range<pass::SubpassRef>) -> pass::SubpassDependency {
+        pass::SubpassDependency {
+            passes,
+            stages: pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT
+                ..pso::PipelineStage::FRAGMENT_SHADER,
+            accesses: image::Access::COLOR_ATTACHMENT_WRITE..image::Access::INPUT_ATTACHMENT_READ,
+        }
+    }
+
+    // a single subpass on top of `attachments`
+    fn validate(subpass: SubpassBuilder) -> Result<(), RenderPassError> {
+        attachments().subpass(subpass).validate()
+    }
+
+    #[test]
+    fn valid_render_passes_pass() {
+        let render_pass = attachments()
+            .subpass(
+                SubpassBuilder::new()
+                    .color(MULTISAMPLED)
+                    .depth_stencil(DEPTH)
+                    .resolve(COLOR),
+            )
+            .subpass(
+                SubpassBuilder::new()
+                    .input(COLOR)
+                    .color(MULTISAMPLED_2)
+                    .preserve(DEPTH),
+            )
+            .dependency(dependency(
+                pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+            ))
+            .dependency(dependency(
+                pass::SubpassRef::Pass(0)..pass::SubpassRef::Pass(1),
+            ))
+            .dependency(dependency(
+                pass::SubpassRef::Pass(1)..pass::SubpassRef::External,
+            ));
+        assert_eq!(render_pass.validate(), Ok(()));
+        assert_eq!(attachments().validate(), Err(RenderPassError::NoSubpasses));
+    }
+
+    #[test]
+    fn attachment_references_must_exist_and_be_unique() {
+        assert_eq!(
+            validate(SubpassBuilder::new().color(COLOR).color(4)),
+            Err(RenderPassError::InvalidAttachment {
+                subpass: 0,
+                attachment: 4,
+            })
+        );
+        assert_eq!(
+            validate(SubpassBuilder::new().color(COLOR).input(7)),
+            Err(RenderPassError::InvalidAttachment {
+                subpass: 0,
+                attachment: 7,
+            })
+        );
+        assert_eq!(
+            validate(SubpassBuilder::new().color(COLOR).color(COLOR)),
+            Err(RenderPassError::DuplicateAttachment {
+                subpass: 0,
+                attachment: COLOR,
+            })
+        );
+    }
+
+    #[test]
+    fn attachments_must_have_the_aspect_they_are_used_for() {
+        assert_eq!(
+            validate(SubpassBuilder::new().color(DEPTH)),
+            Err(RenderPassError::WrongAspect {
+                subpass: 0,
+                attachment: DEPTH,
+            })
+        );
+        assert_eq!(
+            validate(
+                SubpassBuilder::new()
+                    .color(MULTISAMPLED)
+                    .depth_stencil(COLOR)
+            ),
+            Err(RenderPassError::WrongAspect {
+                subpass: 0,
+                attachment: COLOR,
+            })
+        );
+    }
+
+    #[test]
+    fn resolves_must_match_the_colors() {
+        assert_eq!(
+            validate(
+                SubpassBuilder::new()
+                    .color(MULTISAMPLED)
+                    .color(MULTISAMPLED_2)
+                    .resolve(COLOR)
+            ),
+            Err(RenderPassError::ResolveCountMismatch {
+                subpass: 0,
+                colors: 2,
+                resolves: 1,
+            })
+        );
+        // single sampled colors have nothing to resolve, and resolve targets are single sampled
+        assert_eq!(
+            validate(SubpassBuilder::new().color(COLOR).resolve(MULTISAMPLED)),
+            Err(RenderPassError::InvalidResolve {
+                subpass: 0,
+                attachment: MULTISAMPLED,
+            })
+        );
+        assert_eq!(
+            validate(
+                SubpassBuilder::new()
+                    .color(MULTISAMPLED)
+                    .resolve(MULTISAMPLED_2)
+            ),
+            Err(RenderPassError::InvalidResolve {
+                subpass: 0,
+                attachment: MULTISAMPLED_2,
+            })
+        );
+    }
+
+    #[test]
+    fn preserved_attachments_must_not_be_used() {
+        assert_eq!(
+            validate(SubpassBuilder::new().color(COLOR).preserve(COLOR)),
+            Err(RenderPassError::PreservedAttachmentUsed {
+                subpass: 0,
+                attachment: COLOR,
+            })
+        );
+        assert_eq!(
+            validate(
+                SubpassBuilder::new()
+                    .color(MULTISAMPLED)
+                    .input(COLOR)
+                    .preserve(COLOR)
+            ),
+            Err(RenderPassError::PreservedAttachmentUsed {
+                subpass: 0,
+                attachment: COLOR,
+            })
+        );
+    }
+
+    #[test]
+    fn dependencies_must_go_forwards_between_existing_subpasses() {
+        let render_pass = attachments()
+            .subpass(SubpassBuilder::new().color(COLOR))
+            .subpass(SubpassBuilder::new().input(COLOR).color(MULTISAMPLED));
+        let invalid = [
+            pass::SubpassRef::Pass(1)..pass::SubpassRef::Pass(0),
+            pass::SubpassRef::External..pass::SubpassRef::External,
+            pass::SubpassRef::Pass(0)..pass::SubpassRef::Pass(2),
+        ];
+
+        for passes in invalid.iter() {
+            assert_eq!(
+                render_pass
+                    .clone()
+                    .dependency(dependency(passes.clone()))
+                    .validate(),
+                Err(RenderPassError::InvalidDependency { dependency: 0 })
+            );
+        }
+    }
+}
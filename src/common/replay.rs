@@ -0,0 +1,284 @@
+// records the input events of every frame along with how much time the frame advanced the
+// simulation, so a run can be replayed later; feeding the same events and elapsed times to the
+// fixed timestep runs the same updates and interpolates the same way, producing the same frames
+//
+// recordings are text, a `frame <nanoseconds>` line per frame followed by one line per event;
+// an `extent <width> <height> ...` line with the swapchain extent of every window comes before the
+// first frame and before any frame drawn at different extents, the aspect ratio depends on it
+use common::input::{Button, InputEvent};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    // lines are counted from 1
+    Syntax { line: usize },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Io(ref err) => write!(f, "{}", err),
+            ReplayError::Syntax { line } => write!(f, "line {} is not a frame or event", line),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+// width and height in pixels
+pub type Extent = (u32, u32);
+
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    // extents written last, None before the first frame
+    extents: Option<Vec<Extent>>,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> io::Result<InputRecorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "# input recording")?;
+        Ok(InputRecorder {
+            writer,
+            extents: None,
+        })
+    }
+
+    // `extents` are the ones of the windows the frame was drawn to, in order
+    pub fn record_frame(
+        &mut self,
+        elapsed: Duration,
+        extents: &[Extent],
+        events: &[InputEvent],
+    ) -> io::Result<()> {
+        if self.extents.as_deref() != Some(extents) {
+            write!(self.writer, "extent")?;
+            for &(width, height) in extents {
+                write!(self.writer, " {} {}", width, height)?;
+            }
+            writeln!(self.writer)?;
+            self.extents = Some(extents.to_vec());
+        }
+
+        writeln!(
+            self.writer,
+            "frame {}",
+            elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos())
+        )?;
+
+        for event in events {
+            match *event {
+                InputEvent::Button { button, pressed } => {
+                    // keys without a name can't be bound to anything, so they don't matter
+                    if let Some(name) = button.name() {
+                        let state = if pressed { "down" } else { "up" };
+                        writeln!(self.writer, "button {} {}", name, state)?;
+                    }
+                }
+                // floats are printed with as many digits as it takes to read back the same value
+                InputEvent::CursorMoved { x, y } => writeln!(self.writer, "cursor {} {}", x, y)?,
+                InputEvent::CursorLeft => writeln!(self.writer, "cursor_left")?,
+                InputEvent::Scroll(lines) => writeln!(self.writer, "scroll {}", lines)?,
+                InputEvent::Unfocused => writeln!(self.writer, "unfocused")?,
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn parse_event(words: &[&str]) -> Option<InputEvent> {
+    match *words {
+        ["button", name, state] => Some(InputEvent::Button {
+            button: Button::from_name(name)?,
+            pressed: match state {
+                "down" => true,
+                "up" => false,
+                _ => return None,
+            },
+        }),
+        ["cursor", x, y] => Some(InputEvent::CursorMoved {
+            x: x.parse().ok()?,
+            y: y.parse().ok()?,
+        }),
+        ["cursor_left"] => Some(InputEvent::CursorLeft),
+        ["scroll", lines] => Some(InputEvent::Scroll(lines.parse().ok()?)),
+        ["unfocused"] => Some(InputEvent::Unfocused),
+        _ => None,
+    }
+}
+
+// pairs of width and height, at least one and none of them empty
+fn parse_extents(words: &[&str]) -> Option<Vec<Extent>> {
+    if words.is_empty() || !words.len().is_multiple_of(2) {
+        return None;
+    }
+    words
+        .chunks(2)
+        .map(|pair| match (pair[0].parse(), pair[1].parse()) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => Some((width, height)),
+            _ => None,
+        })
+        .collect()
+}
+
+pub struct RecordedFrame {
+    // how much the frame advanced the simulation by
+    pub elapsed: Duration,
+    // of every window, empty for recordings made before extents were recorded
+    pub extents: Vec<Extent>,
+    pub events: Vec<InputEvent>,
+}
+
+pub struct InputReplay {
+    frames: VecDeque<RecordedFrame>,
+}
+
+impl InputReplay {
+    pub fn load(path: &Path) -> Result<InputReplay, ReplayError> {
+        let text = fs::read_to_string(path).map_err(ReplayError::Io)?;
+        let mut frames: VecDeque<RecordedFrame> = VecDeque::new();
+        let mut extents = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let syntax_error = ReplayError::Syntax { line: index + 1 };
+            if let ["frame", nanos] = words[..] {
+                let nanos: u64 = nanos.parse().map_err(|_| syntax_error)?;
+                frames.push_back(RecordedFrame {
+                    elapsed: Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32),
+                    extents: extents.clone(),
+                    events: Vec::new(),
+                });
+                continue;
+            }
+            if words[0] == "extent" {
+                extents = parse_extents(&words[1..]).ok_or(syntax_error)?;
+                continue;
+            }
+
+            let event = parse_event(&words).ok_or(syntax_error)?;
+            match frames.back_mut() {
+                Some(frame) => frame.events.push(event),
+                // events before the first frame
+                None => return Err(ReplayError::Syntax { line: index + 1 }),
+            }
+        }
+
+        Ok(InputReplay { frames })
+    }
+
+    pub fn frames_left(&self) -> usize {
+        self.frames.len()
+    }
+
+    // None once the recording ends
+    pub fn next_frame(&mut self) -> Option<RecordedFrame> {
+        self.frames.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+    use winit::{MouseButton, VirtualKeyCode};
+
+    // a file per test and process, so tests running in parallel don't share one
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("{}-{}.txt", name, process::id()))
+    }
+
+    #[test]
+    fn recorded_frames_replay_unchanged() {
+        let frames = vec![
+            (
+                Duration::new(0, 16_666_667),
+                vec![(1024, 768)],
+                vec![
+                    InputEvent::Button {
+                        button: Button::Key(VirtualKeyCode::W),
+                        pressed: true,
+                    },
+                    InputEvent::CursorMoved { x: 0.1, y: 512.75 },
+                ],
+            ),
+            (
+                Duration::new(1, 5),
+                vec![(1024, 768)],
+                vec![InputEvent::Scroll(-1.5), InputEvent::CursorLeft],
+            ),
+            (
+                Duration::new(0, 0),
+                vec![(800, 600), (1024, 768)],
+                vec![
+                    InputEvent::Button {
+                        button: Button::Mouse(MouseButton::Left),
+                        pressed: false,
+                    },
+                    InputEvent::Unfocused,
+                ],
+            ),
+        ];
+
+        let path = temp_path("replay_round_trip");
+        let mut recorder = InputRecorder::create(&path).unwrap();
+        for &(elapsed, ref extents, ref events) in frames.iter() {
+            recorder.record_frame(elapsed, extents, events).unwrap();
+        }
+        recorder.finish().unwrap();
+        let replay = InputReplay::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let mut replay = replay.unwrap();
+        assert_eq!(replay.frames_left(), frames.len());
+        for (elapsed, extents, events) in frames {
+            let frame = replay.next_frame().unwrap();
+            assert_eq!(frame.elapsed, elapsed);
+            assert_eq!(frame.extents, extents);
+            assert_eq!(frame.events, events);
+        }
+        assert!(replay.next_frame().is_none());
+    }
+
+    #[test]
+    fn malformed_recordings_are_rejected() {
+        let recordings = [
+            ("# input recording\ncursor 1 2\nframe 0\n", 2),
+            ("frame 0\nbutton W sideways\n", 2),
+            ("extent 1024 768\nframe 0\nextent 1024 0\n", 3),
+            ("frame 10\n\nframe soon\n", 3),
+        ];
+
+        for (index, &(text, error_line)) in recordings.iter().enumerate() {
+            let path = temp_path(&format!("replay_malformed_{}", index));
+            fs::write(&path, text).unwrap();
+            let replay = InputReplay::load(&path);
+            fs::remove_file(&path).unwrap();
+
+            match replay {
+                Err(ReplayError::Syntax { line }) => assert_eq!(line, error_line),
+                Err(err) => panic!("unexpected error: {}", err),
+                Ok(_) => panic!("loaded a malformed recording {:?}", text),
+            }
+        }
+    }
+}
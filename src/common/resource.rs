@@ -0,0 +1,256 @@
+// device objects that destroy themselves when dropped, so partially initialized state and early
+// returns (or panics) don't leak; each holds a reference to the device to keep it alive
+use back;
+use common::leak;
+use hal::{pool, Backend, Device, Graphics};
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+pub type DeviceRef = Rc<<back::Backend as Backend>::Device>;
+
+pub struct Owned<T> {
+    device: DeviceRef,
+    // only None once destroyed or released
+    object: Option<T>,
+    destroy: fn(&<back::Backend as Backend>::Device, T),
+    token: leak::Token,
+}
+
+impl<T> Owned<T> {
+    // `destroy` must be the matching `Device::destroy_*` call for `object`, `kind` names it in
+    // leak reports
+    pub fn new(
+        device: &DeviceRef,
+        kind: &'static str,
+        object: T,
+        destroy: fn(&<back::Backend as Backend>::Device, T),
+    ) -> Owned<T> {
+        Owned {
+            device: device.clone(),
+            object: Some(object),
+            destroy,
+            token: leak::track(kind),
+        }
+    }
+
+    // hands the object to a call that destroys it instead of the matching `destroy_*`, e.g.
+    // creating a swapchain destroys the one it replaces; it stays tracked until that call returns,
+    // so a panic in between still shows up as a leak
+    pub fn consume<R, F: FnOnce(T) -> R>(mut self, f: F) -> R {
+        let result = f(self.object.take().unwrap());
+        leak::untrack(&self.token);
+        result
+    }
+}
+
+impl<T> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.object.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for Owned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.object.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for Owned<T> {
+    fn drop(&mut self) {
+        if let Some(object) = self.object.take() {
+            (self.destroy)(&self.device, object);
+            leak::untrack(&self.token);
+        }
+    }
+}
+
+pub type Fence = Owned<<back::Backend as Backend>::Fence>;
+pub type Semaphore = Owned<<back::Backend as Backend>::Semaphore>;
+pub type CommandPool = Owned<pool::CommandPool<back::Backend, Graphics>>;
+pub type QueryPool = Owned<<back::Backend as Backend>::QueryPool>;
+pub type Framebuffer = Owned<<back::Backend as Backend>::Framebuffer>;
+pub type ShaderModule = Owned<<back::Backend as Backend>::ShaderModule>;
+pub type GraphicsPipeline = Owned<<back::Backend as Backend>::GraphicsPipeline>;
+pub type PipelineLayout = Owned<<back::Backend as Backend>::PipelineLayout>;
+pub type DescriptorSetLayout = Owned<<back::Backend as Backend>::DescriptorSetLayout>;
+pub type PipelineCache = Owned<<back::Backend as Backend>::PipelineCache>;
+pub type RenderPass = Owned<<back::Backend as Backend>::RenderPass>;
+pub type ImageView = Owned<<back::Backend as Backend>::ImageView>;
+pub type Swapchain = Owned<<back::Backend as Backend>::Swapchain>;
+pub type Memory = Owned<<back::Backend as Backend>::Memory>;
+pub type Buffer = Owned<<back::Backend as Backend>::Buffer>;
+pub type Image = Owned<<back::Backend as Backend>::Image>;
+pub type DescriptorPool = Owned<<back::Backend as Backend>::DescriptorPool>;
+
+pub fn fence(device: &DeviceRef, fence: <back::Backend as Backend>::Fence) -> Fence {
+    Owned::new(device, "fence", fence, |device, fence| unsafe {
+        device.destroy_fence(fence)
+    })
+}
+
+pub fn semaphore(
+    device: &DeviceRef,
+    semaphore: <back::Backend as Backend>::Semaphore,
+) -> Semaphore {
+    Owned::new(device, "semaphore", semaphore, |device, semaphore| unsafe {
+        device.destroy_semaphore(semaphore)
+    })
+}
+
+pub fn command_pool(
+    device: &DeviceRef,
+    command_pool: pool::CommandPool<back::Backend, Graphics>,
+) -> CommandPool {
+    Owned::new(
+        device,
+        "command pool",
+        command_pool,
+        |device, command_pool| unsafe { device.destroy_command_pool(command_pool.into_raw()) },
+    )
+}
+
+pub fn query_pool(
+    device: &DeviceRef,
+    query_pool: <back::Backend as Backend>::QueryPool,
+) -> QueryPool {
+    Owned::new(
+        device,
+        "query pool",
+        query_pool,
+        |device, query_pool| unsafe { device.destroy_query_pool(query_pool) },
+    )
+}
+
+pub fn framebuffer(
+    device: &DeviceRef,
+    framebuffer: <back::Backend as Backend>::Framebuffer,
+) -> Framebuffer {
+    Owned::new(
+        device,
+        "framebuffer",
+        framebuffer,
+        |device, framebuffer| unsafe { device.destroy_framebuffer(framebuffer) },
+    )
+}
+
+pub fn shader_module(
+    device: &DeviceRef,
+    shader_module: <back::Backend as Backend>::ShaderModule,
+) -> ShaderModule {
+    Owned::new(
+        device,
+        "shader module",
+        shader_module,
+        |device, shader_module| unsafe { device.destroy_shader_module(shader_module) },
+    )
+}
+
+pub fn graphics_pipeline(
+    device: &DeviceRef,
+    pipeline: <back::Backend as Backend>::GraphicsPipeline,
+) -> GraphicsPipeline {
+    Owned::new(
+        device,
+        "graphics pipeline",
+        pipeline,
+        |device, pipeline| unsafe { device.destroy_graphics_pipeline(pipeline) },
+    )
+}
+
+pub fn pipeline_layout(
+    device: &DeviceRef,
+    layout: <back::Backend as Backend>::PipelineLayout,
+) -> PipelineLayout {
+    Owned::new(device, "pipeline layout", layout, |device, layout| unsafe {
+        device.destroy_pipeline_layout(layout)
+    })
+}
+
+pub fn descriptor_set_layout(
+    device: &DeviceRef,
+    layout: <back::Backend as Backend>::DescriptorSetLayout,
+) -> DescriptorSetLayout {
+    Owned::new(
+        device,
+        "descriptor set layout",
+        layout,
+        |device, layout| unsafe { device.destroy_descriptor_set_layout(layout) },
+    )
+}
+
+pub fn pipeline_cache(
+    device: &DeviceRef,
+    pipeline_cache: <back::Backend as Backend>::PipelineCache,
+) -> PipelineCache {
+    Owned::new(
+        device,
+        "pipeline cache",
+        pipeline_cache,
+        |device, pipeline_cache| unsafe { device.destroy_pipeline_cache(pipeline_cache) },
+    )
+}
+
+pub fn render_pass(
+    device: &DeviceRef,
+    render_pass: <back::Backend as Backend>::RenderPass,
+) -> RenderPass {
+    Owned::new(
+        device,
+        "render pass",
+        render_pass,
+        |device, render_pass| unsafe { device.destroy_render_pass(render_pass) },
+    )
+}
+
+pub fn image_view(
+    device: &DeviceRef,
+    image_view: <back::Backend as Backend>::ImageView,
+) -> ImageView {
+    Owned::new(
+        device,
+        "image view",
+        image_view,
+        |device, image_view| unsafe { device.destroy_image_view(image_view) },
+    )
+}
+
+pub fn swapchain(
+    device: &DeviceRef,
+    swapchain: <back::Backend as Backend>::Swapchain,
+) -> Swapchain {
+    Owned::new(device, "swapchain", swapchain, |device, swapchain| unsafe {
+        device.destroy_swapchain(swapchain)
+    })
+}
+
+pub fn memory(device: &DeviceRef, memory: <back::Backend as Backend>::Memory) -> Memory {
+    Owned::new(device, "memory", memory, |device, memory| unsafe {
+        device.free_memory(memory)
+    })
+}
+
+pub fn buffer(device: &DeviceRef, buffer: <back::Backend as Backend>::Buffer) -> Buffer {
+    Owned::new(device, "buffer", buffer, |device, buffer| unsafe {
+        device.destroy_buffer(buffer)
+    })
+}
+
+pub fn image(device: &DeviceRef, image: <back::Backend as Backend>::Image) -> Image {
+    Owned::new(device, "image", image, |device, image| unsafe {
+        device.destroy_image(image)
+    })
+}
+
+pub fn descriptor_pool(
+    device: &DeviceRef,
+    descriptor_pool: <back::Backend as Backend>::DescriptorPool,
+) -> DescriptorPool {
+    Owned::new(
+        device,
+        "descriptor pool",
+        descriptor_pool,
+        |device, descriptor_pool| unsafe { device.destroy_descriptor_pool(descriptor_pool) },
+    )
+}
@@ -0,0 +1,394 @@
+// copies the rendered backbuffer into host visible memory at the end of a frame, and once that
+// frame's fence has signalled converts it and writes it out on another thread, so the only cost to
+// the frames after it is the copy itself; frames are written as screenshots, or as a sequence of
+// numbered pngs or a y4m video when recording
+use back;
+use common::barrier::{Barriers, ImageState};
+use common::memory::{Allocation, MemoryAllocator, MemoryError, ResourceKind, Strategy};
+use common::query::FrameCommandBuffer;
+use common::render_graph::RenderGraph;
+use common::resource::{self, DeviceRef};
+use hal::memory::Properties;
+use hal::{buffer, command, format, image, pso, window, Backend, Device};
+use png;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SCREENSHOT: &str = "screenshot";
+
+// captured frames waiting for the writer thread; once this many are queued, reading back the next
+// one waits for the writer, so a recording that encodes slower than it renders doesn't keep every
+// frame in memory
+const WRITE_QUEUE_FRAMES: usize = 4;
+
+// in the format read by `Bindings::parse`
+pub const DEFAULT_BINDINGS: &str = "\
+screenshot = F12
+";
+
+// swapchain images are already srgb encoded, whether or not the format says so, because that's
+// what the display expects; so converting only means putting the channels in png's order
+fn is_bgra(format: format::Format) -> Option<bool> {
+    match format {
+        format::Format::Bgra8Srgb | format::Format::Bgra8Unorm => Some(true),
+        format::Format::Rgba8Srgb | format::Format::Rgba8Unorm => Some(false),
+        _ => None,
+    }
+}
+
+fn write_png(path: &Path, extent: window::Extent2D, rgba: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        extent.width,
+        extent.height,
+    );
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(rgba)?;
+    Ok(())
+}
+
+// seconds and milliseconds since the epoch keep the names unique and in order
+fn screenshot_path() -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    PathBuf::from(format!(
+        "screenshot-{}.{:03}.png",
+        now.as_secs(),
+        now.subsec_millis()
+    ))
+}
+
+// planar 4:4:4 with bt.601 limited range coefficients, what y4m players assume by default
+fn rgba_to_ycbcr(rgba: &[u8]) -> Vec<u8> {
+    let pixel_count = rgba.len() / 4;
+    let mut planes = vec![0u8; pixel_count * 3];
+    for (index, pixel) in rgba.chunks(4).enumerate() {
+        let r = f32::from(pixel[0]) / 255.0;
+        let g = f32::from(pixel[1]) / 255.0;
+        let b = f32::from(pixel[2]) / 255.0;
+        planes[index] = (16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8;
+        planes[pixel_count + index] = (128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8;
+        planes[2 * pixel_count + index] =
+            (128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8;
+    }
+    planes
+}
+
+// where captured frames go
+#[derive(Clone, Debug)]
+pub enum FrameOutput {
+    // a png per capture, named after the time it was taken
+    Screenshots,
+    // numbered pngs in an existing directory
+    PngSequence(PathBuf),
+    // a single video stream, with the frame rate as a fraction
+    Y4m {
+        path: PathBuf,
+        frame_rate: (u32, u32),
+    },
+}
+
+struct Readback {
+    buffer: resource::Buffer,
+    allocation: Allocation,
+    extent: window::Extent2D,
+    bgra: bool,
+}
+
+struct CapturedFrame {
+    extent: window::Extent2D,
+    bgra: bool,
+    pixels: Vec<u8>,
+}
+
+// runs on the writer thread, frames arrive in the order they were captured
+struct FrameWriter {
+    output: FrameOutput,
+    frames_written: usize,
+    // opened on the first frame, every frame has to have its size
+    y4m: Option<(BufWriter<File>, window::Extent2D)>,
+}
+
+impl FrameWriter {
+    fn write(&mut self, mut frame: CapturedFrame) -> io::Result<()> {
+        for pixel in frame.pixels.chunks_mut(4) {
+            if frame.bgra {
+                pixel.swap(0, 2);
+            }
+            // the window isn't blended with anything, whatever ended up in alpha is meaningless
+            pixel[3] = 0xff;
+        }
+
+        match self.output {
+            FrameOutput::Screenshots => {
+                let path = screenshot_path();
+                write_png(&path, frame.extent, &frame.pixels)?;
+                info!("saved screenshot to {}", path.display());
+            }
+            FrameOutput::PngSequence(ref directory) => {
+                let path = directory.join(format!("frame-{:05}.png", self.frames_written));
+                write_png(&path, frame.extent, &frame.pixels)?;
+            }
+            FrameOutput::Y4m {
+                ref path,
+                frame_rate,
+            } => {
+                if self.y4m.is_none() {
+                    let mut writer = BufWriter::new(File::create(path)?);
+                    writeln!(
+                        writer,
+                        "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                        frame.extent.width, frame.extent.height, frame_rate.0, frame_rate.1
+                    )?;
+                    self.y4m = Some((writer, frame.extent));
+                }
+
+                let (ref mut writer, extent) = *self.y4m.as_mut().unwrap();
+                if frame.extent != extent {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "y4m frames must all have the same size",
+                    ));
+                }
+                writeln!(writer, "FRAME")?;
+                writer.write_all(&rgba_to_ycbcr(&frame.pixels))?;
+            }
+        }
+
+        self.frames_written += 1;
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self.y4m {
+            Some((mut writer, _)) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct Screenshots {
+    device: DeviceRef,
+    // the copy recorded by each frame in flight, read back once its fence has signalled
+    readbacks: Vec<Option<Readback>>,
+    // handed to the writer thread when the first frame is captured, None afterwards
+    output: Option<FrameOutput>,
+    // converting and encoding happen on another thread, in order
+    sender: Option<SyncSender<CapturedFrame>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Screenshots {
+    pub fn new(device: &DeviceRef, frames: usize, output: FrameOutput) -> Screenshots {
+        Screenshots {
+            device: device.clone(),
+            readbacks: (0..frames).map(|_| None).collect(),
+            output: Some(output),
+            sender: None,
+            writer: None,
+        }
+    }
+
+    // most windows never take a screenshot, so the thread is only started for the first one
+    fn start_writer(&mut self) {
+        let output = match self.output.take() {
+            Some(output) => output,
+            None => return,
+        };
+
+        let (sender, receiver) = mpsc::sync_channel::<CapturedFrame>(WRITE_QUEUE_FRAMES);
+        self.sender = Some(sender);
+        self.writer = Some(thread::spawn(move || {
+            let mut writer = FrameWriter {
+                output,
+                frames_written: 0,
+                y4m: None,
+            };
+            // one frame that can't be written doesn't stop the ones after it
+            for frame in receiver {
+                if let Err(err) = writer.write(frame) {
+                    warn!("could not write captured frame: {}", err);
+                }
+            }
+            if let Err(err) = writer.finish() {
+                warn!("could not write captured frames: {}", err);
+            }
+        }));
+    }
+
+    // makes `record_copy` copy the frame's swapchain image, if the swapchain allows it
+    pub unsafe fn capture(
+        &mut self,
+        memory_allocator: &mut MemoryAllocator,
+        frame: usize,
+        format: format::Format,
+        usage: image::Usage,
+        extent: window::Extent2D,
+    ) -> Result<(), MemoryError> {
+        if !usage.contains(image::Usage::TRANSFER_SRC) {
+            warn!("the surface does not allow copying from swapchain images");
+            return Ok(());
+        }
+        let bgra = match is_bgra(format) {
+            Some(bgra) => bgra,
+            None => {
+                warn!("screenshots of {:?} swapchains are not supported", format);
+                return Ok(());
+            }
+        };
+
+        let size = u64::from(extent.width) * u64::from(extent.height) * 4;
+        let mut buffer = resource::buffer(
+            &self.device,
+            self.device
+                .create_buffer(size, buffer::Usage::TRANSFER_DST)
+                .map_err(|_| MemoryError::OutOfMemory)?,
+        );
+        let requirements = self.device.get_buffer_requirements(&buffer);
+        // freed again as soon as the copy has been read, so it can come from the linear pool
+        let allocation = memory_allocator.allocate(
+            requirements,
+            Properties::CPU_VISIBLE | Properties::COHERENT,
+            Strategy::Linear,
+            ResourceKind::Linear,
+        )?;
+        self.device
+            .bind_buffer_memory(allocation.memory(), allocation.offset(), &mut buffer)
+            .map_err(|_| MemoryError::OutOfMemory)?;
+
+        self.readbacks[frame] = Some(Readback {
+            buffer,
+            allocation,
+            extent,
+            bgra,
+        });
+        self.start_writer();
+        Ok(())
+    }
+
+    // call after the render graph was recorded, the image is left the way the graph left it
+    pub unsafe fn record_copy(
+        &self,
+        command_buffer: &mut FrameCommandBuffer,
+        frame: usize,
+        render_graph: &mut RenderGraph,
+        image_index: usize,
+        image: &<back::Backend as Backend>::Image,
+    ) {
+        let readback = match self.readbacks[frame] {
+            Some(ref readback) => readback,
+            None => return,
+        };
+
+        let final_state = render_graph.compiled().backbuffer_usage().image_state();
+        let (tracker, image_id) = render_graph.backbuffer_tracker(image_index);
+        let range = image::SubresourceRange {
+            aspects: format::Aspects::COLOR,
+            levels: 0..1,
+            layers: 0..1,
+        };
+
+        let mut barriers = Barriers::new();
+        tracker.require(
+            &mut barriers,
+            image_id,
+            image,
+            &range,
+            ImageState::new(
+                pso::PipelineStage::TRANSFER,
+                image::Access::TRANSFER_READ,
+                image::Layout::TransferSrcOptimal,
+            ),
+        );
+        barriers.record(command_buffer);
+
+        command_buffer.copy_image_to_buffer(
+            image,
+            image::Layout::TransferSrcOptimal,
+            &readback.buffer,
+            &[command::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_width: readback.extent.width,
+                buffer_height: readback.extent.height,
+                image_layers: image::SubresourceLayers {
+                    aspects: format::Aspects::COLOR,
+                    level: 0,
+                    layers: 0..1,
+                },
+                image_offset: image::Offset { x: 0, y: 0, z: 0 },
+                image_extent: image::Extent {
+                    width: readback.extent.width,
+                    height: readback.extent.height,
+                    depth: 1,
+                },
+            }],
+        );
+
+        let mut barriers = Barriers::new();
+        tracker.require(&mut barriers, image_id, image, &range, final_state);
+        // the fence alone doesn't make the copy visible to the host
+        barriers.buffer(
+            &readback.buffer,
+            (pso::PipelineStage::TRANSFER, buffer::Access::TRANSFER_WRITE),
+            (pso::PipelineStage::HOST, buffer::Access::HOST_READ),
+        );
+        barriers.record(command_buffer);
+    }
+
+    // call once the frame's fence has signalled
+    pub fn finish_frame(&mut self, memory_allocator: &mut MemoryAllocator, frame: usize) {
+        let readback = match self.readbacks[frame].take() {
+            Some(readback) => readback,
+            None => return,
+        };
+
+        let size = readback.extent.width as usize * readback.extent.height as usize * 4;
+        let mut pixels = vec![0u8; size];
+        unsafe {
+            let mapping = readback
+                .allocation
+                .mapping()
+                .expect("host visible memory is not mapped!");
+            ptr::copy_nonoverlapping(mapping, pixels.as_mut_ptr(), size);
+        }
+        let Readback {
+            buffer,
+            allocation,
+            extent,
+            bgra,
+        } = readback;
+        drop(buffer);
+        memory_allocator.free(allocation);
+
+        if let Some(ref sender) = self.sender {
+            // fails only if the writer thread panicked
+            let _ = sender.send(CapturedFrame {
+                extent,
+                bgra,
+                pixels,
+            });
+        }
+    }
+
+    // call once the device is idle; writes out the copies that haven't been read yet and waits
+    // for every frame to be written
+    pub fn finish(&mut self, memory_allocator: &mut MemoryAllocator) {
+        for frame in 0..self.readbacks.len() {
+            self.finish_frame(memory_allocator, frame);
+        }
+        // the writer stops once it has written everything sent before the channel closed, and
+        // none is started after this
+        self.output = None;
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use common::timestep;
+use common::window_mode::WindowMode;
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+pub const DEFAULT_UPDATE_RATE: f64 = 60.0;
+pub const DEFAULT_RECORD_RATE: f64 = 30.0;
+
+// runtime options, set from the command line like `--frames-in-flight 3`
+#[derive(Clone, Debug)]
+pub struct Settings {
+    // upper bound on frames the cpu may record ahead of the gpu; clamped to the swapchain
+    // image count once the swapchain exists
+    pub frames_in_flight: usize,
+    // simulation updates per second, independent of how often frames are drawn
+    pub update_rate: f64,
+    // maximum frames drawn per second, unlimited when not set
+    pub frame_rate_cap: Option<f64>,
+    // per-frame timings are written here as csv on exit
+    pub timings_csv: Option<PathBuf>,
+    // count the samples (and shader invocations, if supported) each draw produces
+    pub draw_queries: bool,
+    // cpu and gpu spans are written here in chrome's trace event format on exit
+    pub trace: Option<PathBuf>,
+    // the compiled render graph is written here in graphviz's dot format, then the program exits
+    // without creating a window or device
+    pub dump_graph: Option<PathBuf>,
+    // action bindings read from here replace the defaults for the actions they mention
+    pub bindings: Option<PathBuf>,
+    // every frame's input events, elapsed time and window extents are written here
+    pub record_input: Option<PathBuf>,
+    // input recorded with `record_input` is replayed from here instead of using the real input
+    // and clock, the program exits once it ends
+    pub replay_input: Option<PathBuf>,
+    // this many frames are rendered offscreen and written to `record_to`, then the program exits
+    // without creating a window; fewer if `replay_input` ends first
+    pub record_frames: Option<usize>,
+    // a `.y4m` file, or otherwise a directory the frames are written to as numbered pngs
+    pub record_to: Option<PathBuf>,
+    // recorded frames per second, each recorded frame advances the simulation by one frame time,
+    // or by its recorded time when replaying input
+    pub record_rate: f64,
+    // the window starts out in this mode, `windowed`, `borderless` or `fullscreen`
+    pub window_mode: WindowMode,
+    // windows opened at startup, all showing the same scene
+    pub windows: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            update_rate: DEFAULT_UPDATE_RATE,
+            frame_rate_cap: None,
+            timings_csv: None,
+            draw_queries: false,
+            trace: None,
+            dump_graph: None,
+            bindings: None,
+            record_input: None,
+            replay_input: None,
+            record_frames: None,
+            record_to: None,
+            record_rate: DEFAULT_RECORD_RATE,
+            window_mode: WindowMode::Windowed,
+            windows: 1,
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> T {
+    value
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("{} expects a valid value", flag))
+}
+
+// rates are divided by, so they have to be positive (and not infinite), and low enough that one
+// step still lasts at least a nanosecond
+fn parse_rate(flag: &str, value: Option<String>) -> f64 {
+    let rate: f64 = parse_value(flag, value);
+    if !(rate > 0.0 && rate.is_finite()) {
+        panic!("{} expects a positive rate", flag);
+    }
+    if timestep::from_secs(1.0 / rate) == Duration::new(0, 0) {
+        panic!("{} expects a rate of at most one per nanosecond", flag);
+    }
+    rate
+}
+
+impl Settings {
+    pub fn from_args() -> Settings {
+        let mut settings = Settings::default();
+        let mut args = env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--frames-in-flight" => {
+                    settings.frames_in_flight = parse_value(&arg, args.next());
+                }
+                "--update-rate" => {
+                    settings.update_rate = parse_rate(&arg, args.next());
+                }
+                "--fps-cap" => {
+                    settings.frame_rate_cap = Some(parse_rate(&arg, args.next()));
+                }
+                "--timings-csv" => {
+                    settings.timings_csv = Some(parse_value(&arg, args.next()));
+                }
+                "--draw-queries" => settings.draw_queries = true,
+                "--trace" => settings.trace = Some(parse_value(&arg, args.next())),
+                "--dump-graph" => settings.dump_graph = Some(parse_value(&arg, args.next())),
+                "--bindings" => settings.bindings = Some(parse_value(&arg, args.next())),
+                "--record-input" => {
+                    settings.record_input = Some(parse_value(&arg, args.next()));
+                }
+                "--replay-input" => {
+                    settings.replay_input = Some(parse_value(&arg, args.next()));
+                }
+                "--record-frames" => {
+                    settings.record_frames = Some(parse_value(&arg, args.next()));
+                }
+                "--record-to" => settings.record_to = Some(parse_value(&arg, args.next())),
+                "--record-rate" => settings.record_rate = parse_rate(&arg, args.next()),
+                "--window-mode" => settings.window_mode = parse_value(&arg, args.next()),
+                "--windows" => settings.windows = parse_value(&arg, args.next()),
+                _ => panic!("unknown argument {}", arg),
+            }
+        }
+
+        if settings.windows == 0 {
+            panic!("--windows expects at least one window");
+        }
+
+        settings
+    }
+
+    // at least one frame has to be in flight, and more frames than swapchain images would
+    // just leave the extra frames waiting on images
+    pub fn validate_frames_in_flight(&mut self, image_count: usize) {
+        let frames_in_flight = self.frames_in_flight.max(1).min(image_count);
+        if frames_in_flight != self.frames_in_flight {
+            warn!(
+                "{} frames in flight requested but the swapchain has {} images, using {}",
+                self.frames_in_flight, image_count, frames_in_flight
+            );
+            self.frames_in_flight = frames_in_flight;
+        }
+    }
+}
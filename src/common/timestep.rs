@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+// never try to catch up on more than this many steps at once, e.g. after the window was
+// dragged or the process was suspended
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+pub fn as_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+pub fn from_secs(seconds: f64) -> Duration {
+    let whole = seconds.trunc();
+    Duration::new(whole as u64, ((seconds - whole) * 1e9) as u32)
+}
+
+// accumulates real elapsed time and hands it out in fixed simulation steps; whatever is left
+// over is the fraction of a step rendering should interpolate by
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    pub fn new(step: Duration) -> FixedTimestep {
+        assert!(step > Duration::new(0, 0), "timestep must be positive!");
+        FixedTimestep {
+            step,
+            accumulator: Duration::new(0, 0),
+        }
+    }
+
+    pub fn from_rate(updates_per_second: f64) -> FixedTimestep {
+        FixedTimestep::new(from_secs(1.0 / updates_per_second))
+    }
+
+    pub fn accumulate(&mut self, elapsed: Duration) {
+        self.accumulator = (self.accumulator + elapsed).min(self.step * MAX_STEPS_PER_FRAME);
+    }
+
+    // call repeatedly, running one update each time it returns true
+    pub fn step(&mut self) -> bool {
+        if self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn delta(&self) -> f32 {
+        as_secs(self.step) as f32
+    }
+
+    // how far between the previous and current simulation state rendering is, in 0..1
+    pub fn alpha(&self) -> f32 {
+        (as_secs(self.accumulator) / as_secs(self.step)) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_steps(timestep: &mut FixedTimestep) -> u32 {
+        let mut steps = 0;
+        while timestep.step() {
+            steps += 1;
+        }
+        steps
+    }
+
+    #[test]
+    fn leftover_time_is_interpolated() {
+        let mut timestep = FixedTimestep::new(Duration::from_millis(10));
+        timestep.accumulate(Duration::from_millis(25));
+        assert_eq!(run_steps(&mut timestep), 2);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-6);
+
+        timestep.accumulate(Duration::from_millis(5));
+        assert_eq!(run_steps(&mut timestep), 1);
+        assert!(timestep.alpha().abs() < 1e-6);
+    }
+
+    #[test]
+    fn long_frames_only_catch_up_a_few_steps() {
+        let mut timestep = FixedTimestep::from_rate(100.0);
+        timestep.accumulate(Duration::from_secs(10));
+        assert_eq!(run_steps(&mut timestep), MAX_STEPS_PER_FRAME);
+        assert!(timestep.alpha().abs() < 1e-6);
+    }
+}
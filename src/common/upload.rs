@@ -0,0 +1,124 @@
+// persistently mapped ring buffer for data that changes every frame, e.g. uniforms bound with
+// dynamic offsets; space used by a frame is recycled once that frame's fence has signalled
+use back;
+use common::memory::{self, Allocation, MemoryAllocator, MemoryError, ResourceKind, Strategy};
+use common::resource::{self, DeviceRef};
+use hal::memory::Properties;
+use hal::{buffer, Adapter, Backend, Device, PhysicalDevice};
+use std::mem;
+use std::ptr;
+
+pub struct UploadRing {
+    buffer: resource::Buffer,
+    // memory the buffer is bound to, None once released
+    allocation: Option<Allocation>,
+    mapping: *mut u8,
+    capacity: u64,
+    alignment: u64,
+    // positions only ever grow, the offset into the buffer is the position modulo the capacity;
+    // everything from the tail up to the head may still be read by the gpu
+    head: u64,
+    tail: u64,
+    // head at the end of each frame in flight's last use of the ring
+    frame_ends: Vec<u64>,
+}
+
+impl UploadRing {
+    // reserves `frame_size` bytes for each frame in flight
+    pub unsafe fn new(
+        adapter: &Adapter<back::Backend>,
+        device: &DeviceRef,
+        memory_allocator: &mut MemoryAllocator,
+        frames: usize,
+        frame_size: u64,
+    ) -> Result<UploadRing, MemoryError> {
+        let alignment = adapter
+            .physical_device
+            .limits()
+            .min_uniform_buffer_offset_alignment
+            .max(4);
+        let capacity = memory::align_up(frame_size, alignment) * frames as u64;
+
+        let mut buffer = resource::buffer(
+            device,
+            device
+                .create_buffer(capacity, buffer::Usage::UNIFORM | buffer::Usage::VERTEX)
+                .map_err(|_| MemoryError::OutOfMemory)?,
+        );
+        let requirements = device.get_buffer_requirements(&buffer);
+        let allocation = memory_allocator.allocate(
+            requirements,
+            Properties::CPU_VISIBLE | Properties::COHERENT,
+            Strategy::FreeList,
+            ResourceKind::Linear,
+        )?;
+        device
+            .bind_buffer_memory(allocation.memory(), allocation.offset(), &mut buffer)
+            .map_err(|_| MemoryError::OutOfMemory)?;
+        let mapping = allocation
+            .mapping()
+            .expect("host visible memory is not mapped!");
+
+        Ok(UploadRing {
+            buffer,
+            allocation: Some(allocation),
+            mapping,
+            capacity,
+            alignment,
+            head: 0,
+            tail: 0,
+            frame_ends: vec![0; frames],
+        })
+    }
+
+    // hands the memory back to the allocator, nothing can be uploaded afterwards; call once the
+    // gpu no longer uses the buffer, e.g. after waiting for the device to be idle
+    pub fn release(&mut self, memory_allocator: &mut MemoryAllocator) {
+        if let Some(allocation) = self.allocation.take() {
+            memory_allocator.free(allocation);
+        }
+    }
+
+    pub fn buffer(&self) -> &<back::Backend as Backend>::Buffer {
+        &self.buffer
+    }
+
+    // call once the frame's fence has signalled, before uploading anything for it
+    pub fn begin_frame(&mut self, frame: usize) {
+        // frames retire in submission order, so everything written up to the end of this frame's
+        // previous use is free again
+        self.tail = self.tail.max(self.frame_ends[frame]);
+    }
+
+    pub fn end_frame(&mut self, frame: usize) {
+        self.frame_ends[frame] = self.head;
+    }
+
+    // copies `data` into the ring and returns its offset in the buffer, or None if the frames in
+    // flight already use all of the space
+    pub fn upload<T: Copy>(&mut self, data: &[T]) -> Option<u64> {
+        if self.allocation.is_none() {
+            return None;
+        }
+        let size = (data.len() * mem::size_of::<T>()) as u64;
+        let mut start = memory::align_up(self.head, self.alignment);
+        // slices never wrap around the end of the buffer
+        if start % self.capacity + size > self.capacity {
+            start = memory::align_up(start, self.capacity);
+        }
+        if start + size - self.tail > self.capacity {
+            return None;
+        }
+
+        self.head = start + size;
+        let offset = start % self.capacity;
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                self.mapping.offset(offset as isize),
+                size as usize,
+            );
+        }
+        Some(offset)
+    }
+}
@@ -0,0 +1,117 @@
+// switches a window between windowed, borderless and fullscreen, putting it back where it was
+// when it returns to windowed
+//
+// borderless covers the monitor with an undecorated window; fullscreen hands the monitor to the
+// window, which winit only supports without a video mode change, but the os still treats it as
+// fullscreen (e.g. hiding the taskbar, or giving it its own space on macos)
+use std::str::FromStr;
+use winit::dpi::{LogicalPosition, LogicalSize};
+use winit::Window;
+
+pub const TOGGLE_FULLSCREEN: &str = "toggle_fullscreen";
+pub const TOGGLE_BORDERLESS: &str = "toggle_borderless";
+
+// in the format read by `Bindings::parse`
+pub const DEFAULT_BINDINGS: &str = "\
+toggle_fullscreen = F11
+toggle_borderless = F10
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowMode {
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+
+impl FromStr for WindowMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<WindowMode, String> {
+        match name {
+            "windowed" => Ok(WindowMode::Windowed),
+            "borderless" => Ok(WindowMode::Borderless),
+            "fullscreen" => Ok(WindowMode::Fullscreen),
+            _ => Err(format!("unknown window mode {}", name)),
+        }
+    }
+}
+
+pub struct WindowModes {
+    mode: WindowMode,
+    // where the window was and how big it was before it left windowed mode
+    windowed: Option<(Option<LogicalPosition>, LogicalSize)>,
+}
+
+impl WindowModes {
+    // for a window that was just created, which is always windowed
+    pub fn new() -> WindowModes {
+        WindowModes {
+            mode: WindowMode::Windowed,
+            windowed: None,
+        }
+    }
+
+    pub fn mode(&self) -> WindowMode {
+        self.mode
+    }
+
+    // the window is resized by this, so the swapchain has to be recreated afterwards; winit
+    // reports that as a resize
+    pub fn set(&mut self, window: &Window, mode: WindowMode) {
+        if mode == self.mode {
+            return;
+        }
+
+        if self.mode == WindowMode::Windowed {
+            self.windowed = window
+                .get_inner_size()
+                .map(|size| (window.get_position(), size));
+        }
+
+        match mode {
+            WindowMode::Windowed => {
+                window.set_fullscreen(None);
+                window.set_decorations(true);
+                if let Some((position, size)) = self.windowed.take() {
+                    window.set_inner_size(size);
+                    if let Some(position) = position {
+                        window.set_position(position);
+                    }
+                }
+            }
+            WindowMode::Borderless => {
+                window.set_fullscreen(None);
+                window.set_decorations(false);
+                // the monitor reports physical pixels, the window takes logical ones
+                let monitor = window.get_current_monitor();
+                let hidpi_factor = monitor.get_hidpi_factor();
+                window.set_position(monitor.get_position().to_logical(hidpi_factor));
+                window.set_inner_size(monitor.get_dimensions().to_logical(hidpi_factor));
+            }
+            WindowMode::Fullscreen => {
+                window.set_decorations(true);
+                window.set_fullscreen(Some(window.get_current_monitor()));
+            }
+        }
+
+        info!("switched window from {:?} to {:?}", self.mode, mode);
+        self.mode = mode;
+    }
+
+    // switches to `mode`, or back to windowed if the window is already in it
+    pub fn toggle(&mut self, window: &Window, mode: WindowMode) {
+        let mode = if self.mode == mode {
+            WindowMode::Windowed
+        } else {
+            mode
+        };
+        self.set(window, mode);
+    }
+}
+
+impl Default for WindowModes {
+    fn default() -> WindowModes {
+        WindowModes::new()
+    }
+}